@@ -0,0 +1,513 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+use uuid::Uuid;
+
+use crate::{
+    database::{get_database, Character, Database, Group},
+    error::CommandError,
+    group::{trim_name, views, GroupView},
+    keymap::{self, Binding},
+};
+
+/// Version of the bundle format written by this build, groups held copies of
+/// their characters in version 1 and members had no slot in version 2
+const BUNDLE_VERSION: u32 = 3;
+
+/// Groups with the metadata of their characters, shared between machines as
+/// a JSON or TOML file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Bundle {
+    pub version: u32,
+    pub groups: Vec<Group>,
+    /// Characters referenced by the members of the groups, matched by their
+    /// name and server on import
+    pub characters: Vec<Character>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<Vec<Binding>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Adds the groups of the bundle to the current ones
+    #[default]
+    Merge,
+    /// Replaces every group, and the keymap when the bundle has one
+    Replace,
+}
+
+/// What to do with a group or character whose name already exists
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keeps the current one
+    #[default]
+    Skip,
+    /// Takes the one from the bundle
+    Overwrite,
+    /// Imports groups under a free name, characters are kept as with `Skip`
+    /// since their name is the in-game one
+    Rename,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = CommandError;
+
+    fn from_str(s: &str) -> Result<Self, CommandError> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            _ => Err(CommandError::Parse(format!(
+                "unknown conflict policy \"{s}\", expected skip, overwrite or rename"
+            ))),
+        }
+    }
+}
+
+/// Bundles the given groups, every group when `group_ids` is empty
+pub fn export(db: &Database, group_ids: &[Uuid], keymap: bool) -> Result<Bundle, CommandError> {
+    let groups = if group_ids.is_empty() {
+        db.groups.clone()
+    } else {
+        group_ids
+            .iter()
+            .map(|id| {
+                db.find_group(*id)
+                    .cloned()
+                    .ok_or_else(|| CommandError::not_found(format!("group {id}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let mut characters = groups
+        .iter()
+        .flat_map(|g| db.members(g))
+        .map(|(_, c)| c.clone())
+        .collect::<Vec<_>>();
+    characters.sort_by_key(|c| c.key());
+    characters.dedup_by(|a, b| a.id == b.id);
+
+    Ok(Bundle {
+        version: BUNDLE_VERSION,
+        groups,
+        characters,
+        keymap: keymap.then(|| db.keymap.clone()),
+    })
+}
+
+/// Adds the bundle to `db`, characters keep their current id so that the
+/// imported groups share them with the existing ones
+pub fn import(
+    db: &mut Database,
+    bundle: Bundle,
+    mode: ImportMode,
+    policy: ConflictPolicy,
+) -> Result<(), CommandError> {
+    // The keymap may call macros that only exist where it was exported
+    if let Some(keymap) = &bundle.keymap {
+        keymap::validate(keymap, &db.macros)
+            .map_err(|e| CommandError::Invalid(format!("invalid keymap: {e}")))?;
+    }
+
+    // Bundle ids of the characters to their local ones
+    let mut ids = HashMap::new();
+
+    for mut character in bundle.characters {
+        let bundle_id = character.id;
+
+        match db.character(&character.key()) {
+            Some(_) if policy == ConflictPolicy::Overwrite => {
+                let current = db.character_entry(&character.key());
+                current.breed.clone_from(&character.breed);
+                current.notes.clone_from(&character.notes);
+            }
+            Some(_) => {}
+            None => {
+                if db.find_character(character.id).is_some() {
+                    character.id = Uuid::new_v4();
+                }
+
+                db.characters.push(character.clone());
+            }
+        }
+
+        ids.insert(bundle_id, db.character_entry(&character.key()).id);
+    }
+
+    if mode == ImportMode::Replace {
+        db.groups.clear();
+        db.active_group = None;
+    }
+
+    for mut group in bundle.groups {
+        group.name = trim_name(&group.name)?.to_owned();
+        group
+            .members
+            .retain_mut(|member| match ids.get(&member.id) {
+                Some(id) => {
+                    member.id = *id;
+                    true
+                }
+                None => false,
+            });
+        // A leader outside of the members would take over when one with
+        // its id is added later
+        group.leader = group
+            .leader
+            .and_then(|id| ids.get(&id).copied())
+            .filter(|id| group.members.iter().any(|m| m.id == *id));
+
+        if let Some(pos) = db.groups.iter().position(|g| g.name == group.name) {
+            match policy {
+                ConflictPolicy::Skip => continue,
+                ConflictPolicy::Overwrite => {
+                    group.id = db.groups[pos].id;
+                    db.groups[pos] = group;
+                    continue;
+                }
+                ConflictPolicy::Rename => group.name = db.free_group_name(&group.name),
+            }
+        }
+
+        if db.find_group(group.id).is_some() {
+            group.id = Uuid::new_v4();
+        }
+
+        db.groups.push(group);
+    }
+
+    if let Some(keymap) = bundle.keymap {
+        if mode == ImportMode::Replace || policy == ConflictPolicy::Overwrite {
+            db.keymap = keymap;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+/// Writes JSON for `.json` files and TOML otherwise
+pub fn write(path: &Path, bundle: &Bundle) -> Result<(), CommandError> {
+    let data = if is_json(path) {
+        serde_json::to_string_pretty(bundle)?
+    } else {
+        toml::to_string(bundle)?
+    };
+
+    fs::write(path, data)?;
+
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Bundle, CommandError> {
+    let data = fs::read_to_string(path)?;
+
+    let mut value: serde_json::Value = if is_json(path) {
+        serde_json::from_str(&data)?
+    } else {
+        toml::from_str(&data)?
+    };
+
+    let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+
+    if version > BUNDLE_VERSION as u64 {
+        return Err(CommandError::Parse(format!(
+            "unsupported bundle version {version}, the latest is {BUNDLE_VERSION}"
+        )));
+    }
+
+    if version < 2 {
+        split_members(&mut value);
+    }
+
+    if version < 3 {
+        assign_slots(&mut value);
+    }
+
+    value["version"] = BUNDLE_VERSION.into();
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Turns the copies of the characters in the groups of a version 1 bundle
+/// into members
+fn split_members(bundle: &mut serde_json::Value) {
+    let groups = bundle.get_mut("groups").and_then(|g| g.as_array_mut());
+
+    for group in groups.into_iter().flatten() {
+        let Some(group) = group.as_object_mut() else {
+            continue;
+        };
+
+        let copies = group.remove("characters");
+        let members = copies
+            .as_ref()
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .map(|copy| {
+                serde_json::json!({
+                    "id": copy.get("id"),
+                    "enabled": copy.get("enabled").and_then(|e| e.as_bool()).unwrap_or(true),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        group.insert("members".to_owned(), members.into());
+    }
+}
+
+/// Members of older bundles take the slot matching their position
+fn assign_slots(bundle: &mut serde_json::Value) {
+    let groups = bundle.get_mut("groups").and_then(|g| g.as_array_mut());
+
+    for group in groups.into_iter().flatten() {
+        let members = group.get_mut("members").and_then(|m| m.as_array_mut());
+
+        for (i, member) in members.into_iter().flatten().enumerate() {
+            if let Some(member) = member.as_object_mut() {
+                member.entry("slot").or_insert((i + 1).into());
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub fn export_bundle(path: String, group_ids: Vec<Uuid>, keymap: bool) -> Result<(), CommandError> {
+    let bundle = {
+        let db = get_database().read()?;
+        export(&db, &group_ids, keymap)?
+    };
+
+    write(Path::new(shellexpand::tilde(&path).as_ref()), &bundle)
+}
+
+#[tauri::command]
+pub fn import_bundle(
+    path: String,
+    mode: ImportMode,
+    policy: ConflictPolicy,
+) -> Result<Vec<GroupView>, CommandError> {
+    let bundle = read(Path::new(shellexpand::tilde(&path).as_ref()))?;
+
+    let groups = {
+        let mut db = get_database().write()?;
+
+        db.update(|db| import(db, bundle, mode, policy))?;
+
+        views(&db)
+    };
+
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{tests::temp_dir, Breed, Member},
+        keymap::Action,
+    };
+
+    fn character(name: &str, breed: Breed) -> Character {
+        Character {
+            breed: Some(breed),
+            ..Character::new(name)
+        }
+    }
+
+    fn source() -> Database {
+        let iop = character("Iop", Breed::Iop);
+        let eni = character("Eni", Breed::Eniripsa);
+
+        Database {
+            groups: vec![
+                Group {
+                    members: vec![
+                        Member {
+                            enabled: false,
+                            ..Member::new(eni.id, 1)
+                        },
+                        Member::new(iop.id, 2),
+                    ],
+                    ..Group::new("duo")
+                },
+                Group::new("empty"),
+            ],
+            characters: vec![iop, eni],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn export_selected_groups() {
+        let db = source();
+        let bundle = export(&db, &[db.groups[0].id], false).unwrap();
+
+        assert_eq!(bundle.groups, vec![db.groups[0].clone()]);
+        assert_eq!(
+            bundle
+                .characters
+                .iter()
+                .map(|c| &c.name)
+                .collect::<Vec<_>>(),
+            vec!["Eni", "Iop"]
+        );
+        assert_eq!(bundle.keymap, None);
+        assert!(export(&db, &[Uuid::new_v4()], false).is_err());
+    }
+
+    #[test]
+    fn round_trip_json_and_toml() {
+        let dir = temp_dir("bundle");
+        let bundle = export(&source(), &[], true).unwrap();
+
+        for file in ["team.json", "team.toml"] {
+            write(&dir.join(file), &bundle).unwrap();
+            assert_eq!(read(&dir.join(file)).unwrap(), bundle);
+        }
+    }
+
+    #[test]
+    fn read_version_1_bundles() {
+        let path = temp_dir("bundle-v1").join("team.json");
+        let iop = Uuid::new_v4();
+
+        let bundle = serde_json::json!({
+            "version": 1,
+            "groups": [{
+                "id": Uuid::new_v4(),
+                "name": "solo",
+                "characters": [{ "id": iop, "name": "Iop", "breed": "Iop", "enabled": false }],
+            }],
+            "characters": [{ "id": iop, "name": "Iop", "breed": "Iop", "enabled": true }],
+        });
+        fs::write(&path, bundle.to_string()).unwrap();
+
+        let bundle = read(&path).unwrap();
+
+        assert_eq!(bundle.version, BUNDLE_VERSION);
+        assert_eq!(bundle.groups[0].members[0].id, iop);
+        assert!(!bundle.groups[0].members[0].enabled);
+        assert_eq!(bundle.groups[0].members[0].slot, 1);
+        assert_eq!(bundle.characters[0].breed, Some(Breed::Iop));
+    }
+
+    #[test]
+    fn merge_shares_existing_characters() {
+        let bundle = export(&source(), &[], false).unwrap();
+
+        let local = character("Iop", Breed::Cra);
+        let mut db = Database {
+            characters: vec![local.clone()],
+            ..Default::default()
+        };
+
+        import(&mut db, bundle, ImportMode::Merge, ConflictPolicy::Skip).unwrap();
+
+        let duo = &db.groups[0];
+        assert_eq!(duo.members[1].id, local.id);
+        assert_eq!(db.find_character(local.id).unwrap().breed, Some(Breed::Cra));
+        assert_eq!(
+            Some(duo.members[0].id),
+            db.character(&"Eni".into()).map(|c| c.id)
+        );
+        assert!(!duo.members[0].enabled);
+        assert_eq!(db.characters.len(), 2);
+    }
+
+    #[test]
+    fn resolve_duplicate_group_names() {
+        let bundle = export(&source(), &[], true).unwrap();
+
+        let mut db = source();
+        db.keymap.clear();
+        import(
+            &mut db,
+            bundle.clone(),
+            ImportMode::Merge,
+            ConflictPolicy::Skip,
+        )
+        .unwrap();
+        assert_eq!(db.groups.len(), 2);
+        assert!(db.keymap.is_empty());
+
+        import(
+            &mut db,
+            bundle.clone(),
+            ImportMode::Merge,
+            ConflictPolicy::Rename,
+        )
+        .unwrap();
+        assert_eq!(
+            db.groups
+                .iter()
+                .map(|g| g.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["duo", "empty", "duo (2)", "empty (2)"]
+        );
+        assert_ne!(db.groups[0].id, db.groups[2].id);
+
+        import(&mut db, bundle, ImportMode::Replace, ConflictPolicy::Skip).unwrap();
+        assert_eq!(db.groups.len(), 2);
+        assert!(!db.keymap.is_empty());
+    }
+
+    #[test]
+    fn reject_invalid_groups() {
+        let mut bundle = export(&source(), &[], false).unwrap();
+        bundle.groups[0].leader = Some(Uuid::new_v4());
+        bundle.groups[1].name = " ".to_owned();
+
+        let mut db = Database::default();
+        assert!(matches!(
+            import(
+                &mut db,
+                bundle.clone(),
+                ImportMode::Merge,
+                ConflictPolicy::Skip
+            ),
+            Err(CommandError::Invalid(_))
+        ));
+
+        bundle.groups[1].name = " solo ".to_owned();
+
+        let mut db = source();
+        import(
+            &mut db,
+            bundle,
+            ImportMode::Merge,
+            ConflictPolicy::Overwrite,
+        )
+        .unwrap();
+
+        assert_eq!(db.groups[0].leader, None);
+        assert_eq!(db.groups[2].name, "solo");
+    }
+
+    #[test]
+    fn reject_invalid_keymaps() {
+        let mut bundle = export(&source(), &[], true).unwrap();
+        bundle.keymap = Some(vec![Binding::new(
+            "F1".parse().unwrap(),
+            Action::Macro("Dance".to_owned()),
+        )]);
+
+        let mut db = source();
+        assert!(matches!(
+            import(
+                &mut db,
+                bundle,
+                ImportMode::Replace,
+                ConflictPolicy::Overwrite
+            ),
+            Err(CommandError::Invalid(_))
+        ));
+        assert_eq!(db.keymap, source().keymap);
+    }
+}
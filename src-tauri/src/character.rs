@@ -0,0 +1,170 @@
+use uuid::Uuid;
+
+use crate::{
+    database::{get_database, timestamp, Character, Database},
+    desktop::{get_windows, Windows},
+    error::CommandError,
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Characters matching every given criteria, sorted by name, the ones with a
+/// window count as seen `now` since `last_seen` is only refreshed once in a
+/// while
+fn find_stale(
+    db: &Database,
+    windows: &Windows,
+    days: Option<u64>,
+    ungrouped: bool,
+    now: u64,
+) -> Result<Vec<Character>, CommandError> {
+    if days.is_none() && !ungrouped {
+        return Err(CommandError::Invalid(
+            "give a number of days or select the characters without group".to_owned(),
+        ));
+    }
+
+    let mut characters = db
+        .characters
+        .iter()
+        .filter(|c| {
+            days.is_none_or(|days| {
+                !windows.values().any(|key| c.has_key(key))
+                    && c.last_seen
+                        .is_none_or(|seen| seen.saturating_add(days.saturating_mul(DAY)) <= now)
+            })
+        })
+        .filter(|c| !ungrouped || !in_group(db, c.id))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    characters.sort_by_key(|c| c.key());
+
+    Ok(characters)
+}
+
+fn in_group(db: &Database, id: Uuid) -> bool {
+    db.groups
+        .iter()
+        .any(|g| g.members.iter().any(|m| m.id == id))
+}
+
+fn sorted(db: &Database) -> Vec<Character> {
+    let mut characters = db.characters.clone();
+    characters.sort_by_key(|c| c.key());
+
+    characters
+}
+
+/// Every known character, archived ones included
+#[tauri::command]
+pub fn get_characters() -> Result<Vec<Character>, CommandError> {
+    let db = get_database().read()?;
+
+    Ok(sorted(&db))
+}
+
+/// Characters not seen for `days`, never seen ones included, and without
+/// group when `ungrouped` is set
+#[tauri::command]
+pub fn get_stale_characters(
+    days: Option<u64>,
+    ungrouped: bool,
+) -> Result<Vec<Character>, CommandError> {
+    let windows = get_windows().read()?;
+    let db = get_database().read()?;
+
+    find_stale(&db, &windows, days, ungrouped, timestamp())
+}
+
+/// Forgets the characters listed by `get_stale_characters` except the
+/// members of a group, returns the removed ones
+#[tauri::command]
+pub fn prune_characters(
+    days: Option<u64>,
+    ungrouped: bool,
+) -> Result<Vec<Character>, CommandError> {
+    // Copied so that the window watcher is not blocked during the save
+    let windows = get_windows().read()?.clone();
+    let mut db = get_database().write()?;
+
+    let pruned = find_stale(&db, &windows, days, ungrouped, timestamp())?
+        .into_iter()
+        .filter(|c| !in_group(&db, c.id))
+        .collect::<Vec<_>>();
+
+    if !pruned.is_empty() {
+        db.update(|db| {
+            db.characters
+                .retain(|c| !pruned.iter().any(|p| p.id == c.id));
+            Ok(())
+        })?;
+    }
+
+    Ok(pruned)
+}
+
+/// Archived characters are left out of the available ones
+#[tauri::command]
+pub fn set_character_archived(id: Uuid, value: bool) -> Result<Vec<Character>, CommandError> {
+    let mut db = get_database().write()?;
+
+    db.update(|db| {
+        db.find_character_mut(id)
+            .ok_or_else(|| CommandError::not_found(format!("character {id}")))?
+            .archived = value;
+        Ok(())
+    })?;
+
+    Ok(sorted(&db))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Group, Member};
+
+    fn names(characters: &[Character]) -> Vec<&str> {
+        characters.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    #[test]
+    fn find_stale_characters() {
+        let now = 100 * DAY;
+        let mut db = Database::default();
+
+        for (name, last_seen) in [
+            ("Iop", Some(now - DAY)),
+            ("Cra", Some(now - 40 * DAY)),
+            ("Eni", None),
+            ("Sram", Some(now - 40 * DAY)),
+        ] {
+            db.characters.push(Character {
+                last_seen,
+                ..Character::new(name)
+            });
+        }
+
+        let mut group = Group::new("duo");
+        group
+            .members
+            .push(Member::new(db.character(&"Sram".into()).unwrap().id, 1));
+        db.groups.push(group);
+
+        let windows = Windows::new();
+        let stale = |days, ungrouped| find_stale(&db, &windows, days, ungrouped, now).unwrap();
+
+        assert_eq!(names(&stale(Some(30), false)), vec!["Cra", "Eni", "Sram"]);
+        assert_eq!(names(&stale(None, true)), vec!["Cra", "Eni", "Iop"]);
+        assert_eq!(names(&stale(Some(30), true)), vec!["Cra", "Eni"]);
+        assert_eq!(names(&stale(Some(u64::MAX), false)), vec!["Eni"]);
+        assert!(find_stale(&db, &windows, None, false, now).is_err());
+
+        // A character in a window is not stale whatever its last_seen
+        let windows = Windows::from([(1, "Cra".into()), (2, "Eni".into())]);
+        assert_eq!(
+            names(&find_stale(&db, &windows, Some(30), false, now).unwrap()),
+            vec!["Sram"]
+        );
+    }
+}
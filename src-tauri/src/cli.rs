@@ -0,0 +1,268 @@
+use anyhow::{anyhow, bail, Result};
+use std::path::PathBuf;
+
+use crate::{
+    bundle::{self, ConflictPolicy, ImportMode},
+    database::get_database,
+    error::CommandError,
+};
+
+pub const USAGE: &str = "Usage: dofus-switcher [options] [command]
+
+Commands:
+  export <file> [--group <name>]... [--keymap]
+      Writes the groups, all of them unless --group is given, to a JSON
+      (.json) or TOML bundle, with the keymap when --keymap is given
+  import <file> [--replace] [--on-conflict skip|overwrite|rename]
+      Adds the groups of a bundle, or replaces every group with --replace.
+      Groups and characters whose name exists are skipped by default
+
+Options:
+  --profile <name>   Profile to load, defaults to $DOFUS_SWITCHER_PROFILE or \"default\"
+  --data-dir <path>  Directory holding the profiles, defaults to
+                     $DOFUS_SWITCHER_DATA_DIR or $XDG_CONFIG_HOME/dofus-switcher
+  -h, --help         Print this help";
+
+/// Runs instead of the app when given
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Export {
+        path: PathBuf,
+        groups: Vec<String>,
+        keymap: bool,
+    },
+    Import {
+        path: PathBuf,
+        mode: ImportMode,
+        policy: ConflictPolicy,
+    },
+}
+
+/// Command line options, they take precedence over the environment
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub profile: Option<String>,
+    pub data_dir: Option<PathBuf>,
+    pub help: bool,
+    pub command: Option<Command>,
+}
+
+impl Args {
+    /// Parses the arguments without the program name, both `--flag value` and
+    /// `--flag=value` are accepted
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+
+        let mut positional = vec![];
+        let mut groups = vec![];
+        let mut keymap = false;
+        let mut mode = None;
+        let mut policy = None;
+
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                positional.push(arg);
+                continue;
+            }
+
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg, None),
+            };
+
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(anyhow!("missing value for {flag}"))
+            };
+
+            match flag.as_str() {
+                "--profile" => parsed.profile = Some(value()?),
+                "--data-dir" => parsed.data_dir = Some(value()?.into()),
+                "--group" => groups.push(value()?),
+                "--keymap" => keymap = true,
+                "--replace" => mode = Some(ImportMode::Replace),
+                "--on-conflict" => policy = Some(value()?.parse()?),
+                "-h" | "--help" => parsed.help = true,
+                _ => bail!("unknown argument \"{flag}\""),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+
+        parsed.command = match positional.next().as_deref() {
+            None => None,
+            Some("export") => Some(Command::Export {
+                path: positional
+                    .next()
+                    .ok_or(anyhow!("missing bundle file"))?
+                    .into(),
+                groups: std::mem::take(&mut groups),
+                keymap: std::mem::take(&mut keymap),
+            }),
+            Some("import") => Some(Command::Import {
+                path: positional
+                    .next()
+                    .ok_or(anyhow!("missing bundle file"))?
+                    .into(),
+                mode: mode.take().unwrap_or_default(),
+                policy: policy.take().unwrap_or_default(),
+            }),
+            Some(command) => bail!("unknown command \"{command}\""),
+        };
+
+        if let Some(arg) = positional.next() {
+            bail!("unexpected argument \"{arg}\"");
+        }
+
+        if !groups.is_empty() || keymap {
+            bail!("--group and --keymap only apply to export");
+        }
+
+        if mode.is_some() || policy.is_some() {
+            bail!("--replace and --on-conflict only apply to import");
+        }
+
+        Ok(parsed)
+    }
+
+    /// Parses the process arguments, printing the usage and exiting when they
+    /// are invalid or help was requested
+    pub fn from_env() -> Self {
+        match Self::parse(std::env::args().skip(1)) {
+            Ok(args) if args.help => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("{e}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+}
+
+/// Runs the command against the active profile
+pub fn run(command: Command) -> Result<(), CommandError> {
+    match command {
+        Command::Export {
+            path,
+            groups,
+            keymap,
+        } => {
+            let bundle = {
+                let db = get_database().read()?;
+
+                let ids = groups
+                    .iter()
+                    .map(|name| {
+                        db.groups
+                            .iter()
+                            .find(|g| &g.name == name)
+                            .map(|g| g.id)
+                            .ok_or_else(|| CommandError::not_found(format!("group {name}")))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                bundle::export(&db, &ids, keymap)?
+            };
+
+            bundle::write(&path, &bundle)?;
+            println!(
+                "Exported {} groups to {}",
+                bundle.groups.len(),
+                path.display()
+            );
+        }
+        Command::Import { path, mode, policy } => {
+            let bundle = bundle::read(&path)?;
+            let mut db = get_database().write()?;
+            let count = db.groups.len();
+
+            db.update(|db| bundle::import(db, bundle, mode, policy))?;
+
+            match mode {
+                ImportMode::Merge => println!("Imported {} groups", db.groups.len() - count),
+                ImportMode::Replace => println!("Replaced the groups by {}", db.groups.len()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parse_flags() {
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+        assert_eq!(
+            parse(&["--profile", "farm", "--data-dir=/tmp/switcher"]).unwrap(),
+            Args {
+                profile: Some("farm".to_owned()),
+                data_dir: Some("/tmp/switcher".into()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            parse(&[
+                "export",
+                "team.json",
+                "--group",
+                "duo",
+                "--group=trio",
+                "--keymap"
+            ])
+            .unwrap()
+            .command,
+            Some(Command::Export {
+                path: "team.json".into(),
+                groups: vec!["duo".to_owned(), "trio".to_owned()],
+                keymap: true,
+            })
+        );
+        assert_eq!(
+            parse(&[
+                "--profile",
+                "farm",
+                "import",
+                "team.toml",
+                "--on-conflict",
+                "rename"
+            ])
+            .unwrap()
+            .command,
+            Some(Command::Import {
+                path: "team.toml".into(),
+                mode: ImportMode::Merge,
+                policy: ConflictPolicy::Rename,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_invalid_flags() {
+        assert!(parse(&["--profile"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["farm"]).is_err());
+        assert!(parse(&["export"]).is_err());
+        assert!(parse(&["export", "team.json", "--replace"]).is_err());
+        assert!(parse(&["import", "team.json", "--on-conflict", "merge"]).is_err());
+        assert!(parse(&["import", "team.json", "other.json"]).is_err());
+    }
+}
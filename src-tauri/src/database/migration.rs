@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::error::CommandError;
+
+/// Upgrades a database from the version matching its index to the next one
+type Migration = fn(&mut toml::Table);
+
+static MIGRATIONS: &[Migration] = &[
+    assign_ids,
+    split_members,
+    list_characters,
+    assign_slots,
+    keep_custom_matchers,
+];
+
+/// Version written by this build
+pub const VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Upgrades `table` to the current schema one version at a time. Returns
+/// whether anything was migrated.
+pub fn migrate(table: &mut toml::Table) -> Result<bool, CommandError> {
+    // Databases written before versioning have no version
+    let version = match table.get("schema_version") {
+        None => 0,
+        Some(toml::Value::Integer(version)) if (0..=VERSION).contains(version) => *version,
+        Some(version) => {
+            return Err(CommandError::Parse(format!(
+                "unsupported schema version {version}, the latest is {VERSION}"
+            )))
+        }
+    };
+
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(table);
+    }
+
+    table.insert("schema_version".to_owned(), VERSION.into());
+
+    Ok(version < VERSION)
+}
+
+/// Gives an id to every group and character, group members share the id of
+/// the character with the same name
+fn assign_ids(table: &mut toml::Table) {
+    let new_id = || toml::Value::String(Uuid::new_v4().to_string());
+
+    let characters = table
+        .entry("characters")
+        .or_insert_with(|| toml::Table::new().into());
+
+    if let Some(characters) = characters.as_table_mut() {
+        for (_, character) in characters.iter_mut() {
+            let Some(character) = character.as_table_mut() else {
+                continue;
+            };
+
+            if !character.contains_key("id") {
+                character.insert("id".to_owned(), new_id());
+            }
+        }
+    }
+
+    let mut character_ids = table
+        .get("characters")
+        .and_then(|c| c.as_table())
+        .map(|characters| {
+            characters
+                .iter()
+                .filter_map(|(name, c)| Some((name.to_owned(), c.get("id")?.clone())))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+
+    let mut orphans = vec![];
+
+    if let Some(groups) = table.get_mut("groups").and_then(|g| g.as_array_mut()) {
+        for group in groups.iter_mut().filter_map(|g| g.as_table_mut()) {
+            if !group.contains_key("id") {
+                group.insert("id".to_owned(), new_id());
+            }
+
+            let Some(members) = group.get_mut("characters").and_then(|c| c.as_array_mut()) else {
+                continue;
+            };
+
+            for member in members.iter_mut().filter_map(|c| c.as_table_mut()) {
+                if member.contains_key("id") {
+                    continue;
+                }
+
+                let Some(name) = member.get("name").and_then(|n| n.as_str()) else {
+                    continue;
+                };
+
+                let id = match character_ids.get(name) {
+                    Some(id) => id.clone(),
+                    None => {
+                        let id = new_id();
+                        character_ids.insert(name.to_owned(), id.clone());
+
+                        let mut character = member.clone();
+                        character.insert("id".to_owned(), id.clone());
+                        orphans.push((name.to_owned(), character));
+
+                        id
+                    }
+                };
+
+                member.insert("id".to_owned(), id);
+            }
+        }
+    }
+
+    if let Some(characters) = table.get_mut("characters").and_then(|c| c.as_table_mut()) {
+        for (name, character) in orphans {
+            characters.insert(name, character.into());
+        }
+    }
+
+    // The active group used to be stored by name
+    if let Some(name) = table.get("active_group").and_then(|g| g.as_str()) {
+        if Uuid::parse_str(name).is_err() {
+            let id = table
+                .get("groups")
+                .and_then(|g| g.as_array())
+                .and_then(|groups| {
+                    groups
+                        .iter()
+                        .find(|g| g.get("name").and_then(|n| n.as_str()) == Some(name))
+                })
+                .and_then(|g| g.get("id").cloned());
+
+            match id {
+                Some(id) => table.insert("active_group".to_owned(), id),
+                None => table.remove("active_group"),
+            };
+        }
+    }
+}
+
+/// Replaces the copies of the characters in the groups by members holding
+/// their id and the settings specific to the group, `enabled` moves from the
+/// characters to the members
+fn split_members(table: &mut toml::Table) {
+    let mut characters = table
+        .remove("characters")
+        .and_then(|c| match c {
+            toml::Value::Table(characters) => Some(characters),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    if let Some(groups) = table.get_mut("groups").and_then(|g| g.as_array_mut()) {
+        for group in groups.iter_mut().filter_map(|g| g.as_table_mut()) {
+            let copies = match group.remove("characters") {
+                Some(toml::Value::Array(copies)) => copies,
+                _ => vec![],
+            };
+
+            let mut members = toml::value::Array::new();
+
+            for mut copy in copies.into_iter().filter_map(|c| match c {
+                toml::Value::Table(copy) => Some(copy),
+                _ => None,
+            }) {
+                let (Some(id), Some(name)) = (copy.get("id").cloned(), copy.get("name").cloned())
+                else {
+                    continue;
+                };
+
+                let enabled = copy.remove("enabled").unwrap_or(true.into());
+
+                let character = characters
+                    .entry(name.as_str().unwrap_or_default())
+                    .or_insert_with(|| copy.clone().into());
+
+                // The breed is global, a copy may have been set when the
+                // character was not
+                if let Some(character) = character.as_table_mut() {
+                    if let Some(breed) = copy.get("breed") {
+                        character.entry("breed").or_insert_with(|| breed.clone());
+                    }
+                }
+
+                let id = character.get("id").cloned().unwrap_or(id);
+
+                let mut member = toml::Table::new();
+                member.insert("id".to_owned(), id);
+                member.insert("enabled".to_owned(), enabled);
+                members.push(member.into());
+            }
+
+            group.insert("members".to_owned(), members.into());
+        }
+    }
+
+    for (_, character) in characters.iter_mut() {
+        if let Some(character) = character.as_table_mut() {
+            character.remove("enabled");
+        }
+    }
+
+    table.insert("characters".to_owned(), characters.into());
+}
+
+/// Characters were keyed by name, they are listed instead since the server
+/// is part of their identity
+fn list_characters(table: &mut toml::Table) {
+    let characters = match table.remove("characters") {
+        Some(toml::Value::Table(characters)) => characters,
+        _ => Default::default(),
+    };
+
+    // Tables are sorted by key
+    let characters = characters
+        .into_iter()
+        .map(|(_, character)| character)
+        .collect::<Vec<_>>();
+
+    table.insert("characters".to_owned(), characters.into());
+}
+
+/// Hotkeys used to focus the n-th online member, members keep the slot
+/// matching their position so that nothing changes while everyone is online
+fn assign_slots(table: &mut toml::Table) {
+    let Some(groups) = table.get_mut("groups").and_then(|g| g.as_array_mut()) else {
+        return;
+    };
+
+    for group in groups.iter_mut().filter_map(|g| g.as_table_mut()) {
+        let Some(members) = group.get_mut("members").and_then(|m| m.as_array_mut()) else {
+            continue;
+        };
+
+        for (i, member) in members.iter_mut().enumerate() {
+            if let Some(member) = member.as_table_mut() {
+                member
+                    .entry("slot")
+                    .or_insert_with(|| (i as i64 + 1).into());
+            }
+        }
+    }
+}
+
+/// Window matchers held the default ones too, they are now added when the
+/// rules are built
+fn keep_custom_matchers(table: &mut toml::Table) {
+    const DEFAULTS: &[(&str, &str)] = &[
+        (
+            r"(?i)^(dofus retro|dofusretro(\.exe)?)$",
+            r"^(?P<character>\S+) - Dofus Retro",
+        ),
+        (
+            r"(?i)^(dofus(\.exe)?|steam_app_\d+)$",
+            r"^(?P<character>\S+) - (?:(?P<breed>[^\s\d-][^-]*?) - )?(?:(?:Niveau|Level) (?P<level>\d+) - )?",
+        ),
+    ];
+
+    let Some(matchers) = table
+        .get_mut("settings")
+        .and_then(|s| s.get_mut("matchers"))
+        .and_then(|m| m.as_array_mut())
+    else {
+        return;
+    };
+
+    matchers.retain(|matcher| {
+        let field = |name| matcher.get(name).and_then(|v| v.as_str());
+
+        !DEFAULTS
+            .iter()
+            .any(|(class, title)| field("class") == Some(class) && field("title") == Some(title))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{parse, Breed, Character, Database},
+        desktop::FocusStrategy,
+        keymap::Action,
+    };
+
+    fn load(data: &str) -> (Database, bool) {
+        parse(data).unwrap()
+    }
+
+    fn character<'a>(db: &'a Database, name: &str) -> &'a Character {
+        db.character(&name.into()).unwrap()
+    }
+
+    #[test]
+    fn migrate_v0() {
+        let (db, migrated) = load(include_str!("fixtures/v0.toml"));
+        let group = &db.groups[0];
+
+        assert!(migrated);
+        assert_eq!(db.schema_version, VERSION);
+        assert_eq!(group.name, "Team");
+        assert_eq!(group.members[0].id, character(&db, "Iop-Leader").id);
+        assert_eq!(character(&db, "Iop-Leader").breed, Some(Breed::Iop));
+        // Members missing from the characters are added to them
+        assert_eq!(group.members[1].id, character(&db, "Eni-Heal").id);
+        assert!(!group.members[1].enabled);
+        assert!(db.character(&"Cra-Alt".into()).is_some());
+        assert_eq!(db.active_group, None);
+    }
+
+    #[test]
+    fn migrate_v0_with_settings() {
+        let (db, migrated) = load(include_str!("fixtures/v0-settings.toml"));
+
+        assert!(migrated);
+        assert_eq!(db.active_group, Some(db.groups[1].id));
+        assert_eq!(db.keymap.len(), 2);
+        assert_eq!(db.keymap[0].action, Action::Focus(1));
+        assert_eq!(db.settings.focus_timeout, 500);
+        assert_eq!(db.settings.focus_strategy, FocusStrategy::Ewmh);
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let (db, migrated) = load(include_str!("fixtures/v1.toml"));
+        let iop = character(&db, "Iop-Leader");
+
+        assert!(migrated);
+        assert_eq!(db.schema_version, VERSION);
+        assert_eq!(db.active_group, Some(db.groups[0].id));
+        assert_eq!(iop.id.to_string(), "6a1f0d52-8c3b-4a3e-a5f4-2d9b7e1c4f21");
+        assert_eq!(db.groups[0].members[0].id, iop.id);
+        assert!(db.groups[0].members[0].enabled);
+        assert_eq!(db.keymap[0].action, Action::Focus(1));
+        assert!(db.settings.auto_group);
+    }
+
+    #[test]
+    fn migrate_v1_with_shared_characters() {
+        let (db, migrated) = load(include_str!("fixtures/v1-shared.toml"));
+        let iop = character(&db, "Iop-Leader");
+
+        assert!(migrated);
+
+        // Copies become members that keep their own enabled flag
+        assert_eq!(db.groups[0].members[0].id, iop.id);
+        assert!(db.groups[0].members[0].enabled);
+        assert_eq!(db.groups[1].members[0].id, iop.id);
+        assert!(!db.groups[1].members[0].enabled);
+
+        // The breed only set on a copy is kept on the character
+        assert_eq!(character(&db, "Cra-Alt").breed, Some(Breed::Cra));
+        assert_eq!(db.groups[1].members[1].id, character(&db, "Cra-Alt").id);
+        assert_eq!(db.characters.len(), 2);
+
+        // Slots follow the order of the members
+        assert_eq!(db.groups[1].members[0].slot, 1);
+        assert_eq!(db.groups[1].members[1].slot, 2);
+    }
+
+    #[test]
+    fn migrate_v2() {
+        let (db, migrated) = load(include_str!("fixtures/v2.toml"));
+        let members = &db.groups[0].members;
+
+        assert!(migrated);
+        assert_eq!(db.schema_version, VERSION);
+        assert_eq!(db.characters.len(), 2);
+        assert_eq!(members[0].id, character(&db, "Iop-Leader").id);
+        assert_eq!(members[1].id, character(&db, "Cra-Alt").id);
+        assert!(!members[1].enabled);
+        assert_eq!((members[0].slot, members[1].slot), (1, 2));
+    }
+
+    #[test]
+    fn migrate_v3() {
+        let (db, migrated) = load(include_str!("fixtures/v3.toml"));
+        let members = &db.groups[0].members;
+
+        assert!(migrated);
+        assert_eq!(db.schema_version, VERSION);
+        // Characters sharing a name on different servers stay apart
+        assert_eq!(db.characters.len(), 2);
+        assert_eq!(db.characters[1].server.as_deref(), Some("Imagiro"));
+        assert_eq!((members[0].slot, members[1].slot), (1, 2));
+        assert_eq!(db.settings.focus_strategy, FocusStrategy::Ewmh);
+    }
+
+    #[test]
+    fn migrate_v4() {
+        let (db, migrated) = load(include_str!("fixtures/v4.toml"));
+
+        assert!(migrated);
+        assert_eq!(db.schema_version, VERSION);
+        // Slots already assigned are kept
+        assert_eq!(db.groups[0].members[0].slot, 2);
+        // Only the custom matchers are left
+        assert_eq!(db.settings.matchers.len(), 1);
+        assert_eq!(db.settings.matchers[0].class, "^steam_app_1234$");
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let db = Database::default();
+        let (back, migrated) = load(&toml::to_string(&db).unwrap());
+
+        assert!(!migrated);
+        assert_eq!(back.schema_version, VERSION);
+    }
+
+    #[test]
+    fn refuse_newer_versions() {
+        let mut table = toml::from_str::<toml::Table>(&format!(
+            "schema_version = {}\ngroups = []",
+            VERSION + 1
+        ))
+        .unwrap();
+
+        assert!(migrate(&mut table).is_err());
+    }
+}
@@ -0,0 +1,799 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock, PoisonError, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tauri::{AppHandle, Emitter};
+use uuid::Uuid;
+
+mod migration;
+mod sync;
+
+pub use sync::{report, resolve_conflict, watch};
+
+use crate::{
+    desktop,
+    error::CommandError,
+    keymap::{default_bindings, Binding},
+    macros::{default_macros, Macro},
+    profile,
+    settings::Settings,
+};
+
+/// Number of backups kept next to the database
+const BACKUPS: usize = 3;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Breed {
+    Ecaflip,
+    Eniripsa,
+    Iop,
+    Cra,
+    Feca,
+    Sacrieur,
+    Sadida,
+    Osamodas,
+    Enutrof,
+    Sram,
+    Xelor,
+    Pandawa,
+    Roublard,
+    Zobal,
+    Steamer,
+    Eliotrope,
+    Huppermage,
+    Ouginak,
+    Forgelance,
+}
+
+impl Breed {
+    /// Reads the French or English name of a breed, ignoring case and
+    /// accents
+    pub fn parse(name: &str) -> Option<Breed> {
+        let name = name
+            .trim()
+            .to_lowercase()
+            .replace(['é', 'è', 'ê'], "e")
+            .replace('â', "a")
+            .replace(['-', ' '], "");
+
+        let breed = match name.as_str() {
+            "ecaflip" => Breed::Ecaflip,
+            "eniripsa" => Breed::Eniripsa,
+            "iop" => Breed::Iop,
+            "cra" => Breed::Cra,
+            "feca" => Breed::Feca,
+            "sacrieur" | "sacrier" => Breed::Sacrieur,
+            "sadida" => Breed::Sadida,
+            "osamodas" => Breed::Osamodas,
+            "enutrof" => Breed::Enutrof,
+            "sram" => Breed::Sram,
+            "xelor" => Breed::Xelor,
+            "pandawa" => Breed::Pandawa,
+            "roublard" | "rogue" => Breed::Roublard,
+            "zobal" | "masqueraider" => Breed::Zobal,
+            "steamer" | "foggernaut" => Breed::Steamer,
+            "eliotrope" => Breed::Eliotrope,
+            "huppermage" => Breed::Huppermage,
+            "ouginak" => Breed::Ouginak,
+            "forgelance" => Breed::Forgelance,
+            _ => return None,
+        };
+
+        Some(breed)
+    }
+}
+
+/// Identifies a character in game, the server tells apart characters sharing
+/// a name when the window title shows it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CharacterKey {
+    pub name: String,
+    pub server: Option<String>,
+}
+
+impl CharacterKey {
+    pub fn new(name: impl Into<String>, server: Option<String>) -> Self {
+        Self {
+            name: name.into(),
+            server,
+        }
+    }
+}
+
+impl From<&str> for CharacterKey {
+    fn from(name: &str) -> Self {
+        Self::new(name, None)
+    }
+}
+
+impl fmt::Display for CharacterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.server {
+            Some(server) => write!(f, "{} ({server})", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Settings shared by every group the character is a member of
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Character {
+    pub id: Uuid,
+    pub name: String,
+    pub breed: Option<Breed>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// Level shown in the window title when it was last seen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u32>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+    /// Unix time at which a window of the character was last open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    /// Hidden from the available characters, its groups still use it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool,
+}
+
+impl Character {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            breed: None,
+            server: None,
+            level: None,
+            notes: Default::default(),
+            last_seen: None,
+            archived: false,
+        }
+    }
+
+    pub fn key(&self) -> CharacterKey {
+        CharacterKey::new(self.name.to_owned(), self.server.clone())
+    }
+
+    /// Whether a window showing `key` is one of the character, a missing
+    /// server on either side matches any server since titles do not always
+    /// show it
+    pub fn has_key(&self, key: &CharacterKey) -> bool {
+        self.name == key.name
+            && (self.server.is_none() || key.server.is_none() || self.server == key.server)
+    }
+}
+
+impl From<&CharacterKey> for Character {
+    fn from(key: &CharacterKey) -> Self {
+        Self {
+            server: key.server.clone(),
+            ..Character::new(key.name.to_owned())
+        }
+    }
+}
+
+/// A character in a group, with the settings specific to that group
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    /// Id of the character in `Database::characters`
+    pub id: Uuid,
+    pub enabled: bool,
+    /// Focused by the `Focus` hotkey with this number, starting at 1. It is
+    /// kept while the character is offline
+    pub slot: usize,
+}
+
+impl Member {
+    pub fn new(id: Uuid, slot: usize) -> Self {
+        Self {
+            id,
+            enabled: true,
+            slot,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    pub id: Uuid,
+    pub name: String,
+    pub members: Vec<Member>,
+    /// Character leading travels, invites and clicks, the first member when
+    /// unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leader: Option<Uuid>,
+}
+
+impl Group {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            members: Default::default(),
+            leader: None,
+        }
+    }
+
+    /// The designated leader if it is still a member, the first member
+    /// otherwise
+    pub fn leader(&self) -> Option<Uuid> {
+        self.leader
+            .filter(|id| self.members.iter().any(|m| m.id == *id))
+            .or_else(|| self.members.first().map(|m| m.id))
+    }
+
+    /// The lowest slot not taken by a member
+    pub fn free_slot(&self) -> usize {
+        (1..)
+            .find(|slot| !self.members.iter().any(|m| m.slot == *slot))
+            .expect("there is always a free slot")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Database {
+    /// Bumped by every migration, see `migration::VERSION`
+    pub schema_version: i64,
+    pub groups: Vec<Group>,
+    /// Every character ever seen, unique by `CharacterKey`
+    pub characters: Vec<Character>,
+    #[serde(default = "default_bindings")]
+    pub keymap: Vec<Binding>,
+    #[serde(default = "default_macros")]
+    pub macros: Vec<Macro>,
+    #[serde(default)]
+    pub settings: Settings,
+    /// Group the hotkeys act on when auto group is off
+    #[serde(default)]
+    pub active_group: Option<Uuid>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            schema_version: migration::VERSION,
+            groups: Default::default(),
+            characters: Default::default(),
+            keymap: default_bindings(),
+            macros: default_macros(),
+            settings: Default::default(),
+            active_group: None,
+        }
+    }
+}
+
+impl Database {
+    /// Loads the active profile
+    pub fn new() -> Self {
+        Self::open(&profile::get_profile_path())
+    }
+
+    /// Loads the database at `path`, replacing the warning of the previous one
+    pub fn open(path: &Path) -> Self {
+        let (db, mut warning) = Self::load(path);
+
+        if let Err(e) = desktop::set_matchers(&db.settings.matchers) {
+            let _ = desktop::set_matchers(&[]);
+            let matchers = format!("{e}, only the default ones are used");
+            warning = Some(match warning {
+                Some(warning) => format!("{warning}. {matchers}"),
+                None => matchers,
+            });
+        }
+
+        if let Ok(mut current) = WARNING.write() {
+            *current = warning;
+        }
+
+        sync::set_base(sync::read(path).ok().flatten());
+
+        db
+    }
+
+    /// Reads the database at `path`. A file that cannot be parsed is moved
+    /// aside and replaced by the most recent backup that can, the returned
+    /// warning tells the user what happened. Saves are refused while a file
+    /// that could not be read or moved aside is in the way.
+    fn load(path: &Path) -> (Self, Option<String>) {
+        set_unreadable(path, false);
+
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return (Default::default(), None),
+            Err(e) => {
+                set_unreadable(path, true);
+                let warning = format!(
+                    "{} could not be read: {e}, nothing is saved until it is reloaded or reset",
+                    path.display()
+                );
+                return (Default::default(), Some(warning));
+            }
+        };
+
+        let error = match parse(&data) {
+            Ok((db, migrated)) => {
+                // A failed save is retried by the next change
+                if migrated {
+                    let _ = db.save_to(path);
+                }
+
+                return (db, None);
+            }
+            Err(e) => e,
+        };
+
+        let broken = with_suffix(path, &format!(".broken-{}", timestamp()));
+
+        if let Err(e) = fs::rename(path, &broken) {
+            set_unreadable(path, true);
+            let warning = format!(
+                "{} is invalid ({error}) and could not be moved aside: {e}, nothing is saved \
+                 until it is reloaded or reset",
+                path.display()
+            );
+            return (Default::default(), Some(warning));
+        }
+
+        let mut warning = format!(
+            "{} is invalid ({error}), it was moved to {}",
+            path.display(),
+            broken.display()
+        );
+
+        for n in 1..=BACKUPS {
+            let backup = backup_path(path, n);
+
+            let Some((db, _)) = fs::read_to_string(&backup)
+                .ok()
+                .and_then(|data| parse(&data).ok())
+            else {
+                continue;
+            };
+
+            let _ = db.save_to(path);
+            warning.push_str(&format!(" and {} was restored", backup.display()));
+
+            return (db, Some(warning));
+        }
+
+        warning.push_str(" and no backup could be restored");
+
+        (Default::default(), Some(warning))
+    }
+
+    /// The selected group, falling back to the first one
+    pub fn active_group(&self) -> Option<&Group> {
+        self.active_group
+            .and_then(|id| self.find_group(id))
+            .or(self.groups.first())
+    }
+
+    pub fn find_group(&self, id: Uuid) -> Option<&Group> {
+        self.groups.iter().find(|g| g.id == id)
+    }
+
+    pub fn find_group_mut(&mut self, id: Uuid) -> Option<&mut Group> {
+        self.groups.iter_mut().find(|g| g.id == id)
+    }
+
+    pub fn find_character(&self, id: Uuid) -> Option<&Character> {
+        self.characters.iter().find(|c| c.id == id)
+    }
+
+    pub fn find_character_mut(&mut self, id: Uuid) -> Option<&mut Character> {
+        self.characters.iter_mut().find(|c| c.id == id)
+    }
+
+    /// Index of the character with exactly this key, or else of the first
+    /// one matching it
+    fn character_position(&self, key: &CharacterKey) -> Option<usize> {
+        self.characters
+            .iter()
+            .position(|c| c.key() == *key)
+            .or_else(|| self.characters.iter().position(|c| c.has_key(key)))
+    }
+
+    pub fn character(&self, key: &CharacterKey) -> Option<&Character> {
+        self.character_position(key)
+            .map(|pos| &self.characters[pos])
+    }
+
+    /// The character with this key, created when it was never seen. A
+    /// character saved without server takes the one of the key
+    pub fn character_entry(&mut self, key: &CharacterKey) -> &mut Character {
+        let pos = match self.character_position(key) {
+            Some(pos) => pos,
+            None => {
+                self.characters.push(key.into());
+                self.characters.len() - 1
+            }
+        };
+
+        let character = &mut self.characters[pos];
+
+        if character.server.is_none() {
+            character.server.clone_from(&key.server);
+        }
+
+        character
+    }
+
+    /// Members of the group with their character, in group order
+    pub fn members<'a>(
+        &'a self,
+        group: &'a Group,
+    ) -> impl Iterator<Item = (&'a Member, &'a Character)> {
+        group
+            .members
+            .iter()
+            .filter_map(|m| self.find_character(m.id).map(|c| (m, c)))
+    }
+
+    /// `name (2)`, `name (3)`... whichever is not taken yet
+    pub fn free_group_name(&self, name: &str) -> String {
+        (2..)
+            .map(|n| format!("{name} ({n})"))
+            .find(|candidate| !self.groups.iter().any(|g| &g.name == candidate))
+            .expect("there is always a free name")
+    }
+
+    /// Applies `change` to a copy of the database and saves it, the database
+    /// is only replaced once the copy is on disk
+    pub fn update<T>(
+        &mut self,
+        change: impl FnOnce(&mut Database) -> Result<T, CommandError>,
+    ) -> Result<T, CommandError> {
+        let mut updated = self.clone();
+        let value = change(&mut updated)?;
+
+        updated.save()?;
+        *self = updated;
+
+        Ok(value)
+    }
+
+    /// Writes the database to the active profile after merging the changes
+    /// made to the file by another program
+    pub fn save(&mut self) -> Result<(), CommandError> {
+        let path = profile::get_profile_path();
+
+        sync::sync(self, &path)?;
+        self.save_to(&path)?;
+        sync::set_base(Some(self.clone()));
+
+        Ok(())
+    }
+
+    /// Writes to a temporary file renamed over `path`, so that a crash never
+    /// leaves a truncated database behind
+    fn save_to(&self, path: &Path) -> Result<(), CommandError> {
+        if is_unreadable(path) {
+            return Err(CommandError::Io(format!(
+                "{} could not be loaded, reload or reset the database before changing it",
+                path.display()
+            )));
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let data = toml::to_string(self)?;
+        let tmp = with_suffix(path, ".tmp");
+
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+
+        rotate_backups(path);
+        fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// `data.toml.1` is the most recent backup
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".{n}"))
+}
+
+/// Seconds since the unix epoch
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Files that could not be loaded, saving over them would lose what they
+/// hold
+static UNREADABLE: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+fn set_unreadable(path: &Path, value: bool) {
+    let mut unreadable = UNREADABLE.lock().unwrap_or_else(PoisonError::into_inner);
+
+    unreadable.retain(|p| p != path);
+    if value {
+        unreadable.push(path.to_owned());
+    }
+}
+
+fn is_unreadable(path: &Path) -> bool {
+    UNREADABLE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .any(|p| p == path)
+}
+
+/// Files whose backups were rotated since the app started
+static ROTATED: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+/// Keeps a copy of the file as it was before its first save since the app
+/// started, along with the ones from the previous launches. Loads and later
+/// saves leave the backups alone, so that a bad write cannot push out every
+/// good copy.
+fn rotate_backups(path: &Path) {
+    let mut rotated = ROTATED.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if rotated.iter().any(|p| p == path) || !path.exists() {
+        return;
+    }
+
+    rotated.push(path.to_owned());
+
+    for n in (1..BACKUPS).rev() {
+        let _ = fs::rename(backup_path(path, n), backup_path(path, n + 1));
+    }
+
+    let _ = fs::copy(path, backup_path(path, 1));
+}
+
+fn parse(data: &str) -> Result<(Database, bool), CommandError> {
+    let mut table = toml::from_str::<toml::Table>(data)?;
+    let migrated = migration::migrate(&mut table)?;
+    let db = toml::Value::Table(table).try_into::<Database>()?;
+
+    Ok((db, migrated))
+}
+
+pub static DATABASE: OnceLock<RwLock<Database>> = OnceLock::new();
+
+pub fn get_database() -> &'static RwLock<Database> {
+    DATABASE.get_or_init(|| Database::new().into())
+}
+
+static WARNING: RwLock<Option<String>> = RwLock::new(None);
+
+/// Tells the user when the database was broken and had to be restored
+#[tauri::command]
+pub fn get_database_warning() -> Option<String> {
+    // The warning is only known once the database is loaded
+    let _ = get_database();
+
+    WARNING.read().ok().and_then(|warning| warning.clone())
+}
+
+/// Loads the file of the active profile again, e.g. once it can be read
+#[tauri::command]
+pub fn reload_database(app: AppHandle) -> Result<(), CommandError> {
+    {
+        let mut db = get_database().write()?;
+        *db = Database::open(&profile::get_profile_path());
+    }
+
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    let _ = app.emit("database-changed", ());
+
+    Ok(())
+}
+
+/// Moves the file of the active profile aside and starts over from an empty
+/// database
+#[tauri::command]
+pub fn reset_database(app: AppHandle) -> Result<(), CommandError> {
+    let path = profile::get_profile_path();
+
+    {
+        let mut db = get_database().write()?;
+
+        if path.exists() {
+            fs::rename(
+                &path,
+                with_suffix(&path, &format!(".broken-{}", timestamp())),
+            )?;
+        }
+
+        *db = Database::open(&path);
+    }
+
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    let _ = app.emit("database-changed", ());
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An empty directory unique to the test
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dofus-switcher-{name}-{}", std::process::id()));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    pub(crate) fn database(group: &str) -> Database {
+        Database {
+            groups: vec![Group::new(group)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_breed_names() {
+        assert_eq!(Breed::parse("Crâ"), Some(Breed::Cra));
+        assert_eq!(Breed::parse("féca"), Some(Breed::Feca));
+        assert_eq!(Breed::parse("Sacrier"), Some(Breed::Sacrieur));
+        assert_eq!(Breed::parse("Foggernaut"), Some(Breed::Steamer));
+        assert_eq!(Breed::parse("ROUBLARD"), Some(Breed::Roublard));
+        assert_eq!(Breed::parse("Dofus"), None);
+    }
+
+    #[test]
+    fn update_keeps_the_database_on_errors() {
+        let mut db = database("duo");
+
+        let result = db.update(|db| {
+            db.groups.clear();
+            Err::<(), _>(CommandError::Invalid("nope".to_owned()))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(db.groups[0].name, "duo");
+    }
+
+    #[test]
+    fn save_replaces_the_file() {
+        let path = temp_dir("save").join("data.toml");
+
+        database("duo").save_to(&path).unwrap();
+        database("trio").save_to(&path).unwrap();
+
+        let (db, warning) = Database::load(&path);
+
+        assert_eq!(warning, None);
+        assert_eq!(db.groups[0].name, "trio");
+        assert!(!with_suffix(&path, ".tmp").exists());
+    }
+
+    fn backups(path: &Path) -> Vec<Option<String>> {
+        (1..=BACKUPS + 1)
+            .map(|n| {
+                fs::read_to_string(backup_path(path, n))
+                    .ok()
+                    .and_then(|data| parse(&data).ok())
+                    .map(|(db, _)| db.groups[0].name.to_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rotate_backups_once_per_session() {
+        let path = temp_dir("rotate").join("data.toml");
+
+        // Left by the previous launches
+        for (n, name) in [(1, "b"), (2, "c"), (3, "d")] {
+            fs::write(
+                backup_path(&path, n),
+                toml::to_string(&database(name)).unwrap(),
+            )
+            .unwrap();
+        }
+
+        fs::write(&path, toml::to_string(&database("a")).unwrap()).unwrap();
+
+        for _ in 0..5 {
+            Database::load(&path);
+        }
+
+        let previous = vec![
+            Some("b".to_owned()),
+            Some("c".to_owned()),
+            Some("d".to_owned()),
+            None,
+        ];
+        assert_eq!(backups(&path), previous);
+
+        for name in ["e", "f", "g"] {
+            database(name).save_to(&path).unwrap();
+        }
+
+        assert_eq!(
+            backups(&path),
+            vec![
+                Some("a".to_owned()),
+                Some("b".to_owned()),
+                Some("c".to_owned()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn load_restores_the_last_good_backup() {
+        let dir = temp_dir("restore");
+        let path = dir.join("data.toml");
+
+        database("duo").save_to(&path).unwrap();
+        database("duo").save_to(&path).unwrap();
+        fs::write(&path, "groups = [").unwrap();
+
+        let (db, warning) = Database::load(&path);
+
+        assert_eq!(db.groups[0].name, "duo");
+        assert!(warning.unwrap().contains("data.toml.1 was restored"));
+        assert_eq!(Database::load(&path).0.groups[0].name, "duo");
+
+        let broken = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name().to_string_lossy().contains(".broken-"))
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(broken.path()).unwrap(), "groups = [");
+    }
+
+    #[test]
+    fn refuse_saves_over_unreadable_files() {
+        // Reading a directory fails whatever the permissions, even as root
+        let path = temp_dir("unreadable-db").join("data.toml");
+        fs::create_dir(&path).unwrap();
+
+        let (db, warning) = Database::load(&path);
+
+        assert!(db.groups.is_empty());
+        assert!(warning.unwrap().contains("could not be read"));
+        assert!(database("duo").save_to(&path).is_err());
+        assert!(path.is_dir());
+
+        // Until it is loaded again
+        fs::remove_dir(&path).unwrap();
+        Database::load(&path);
+        database("duo").save_to(&path).unwrap();
+    }
+
+    #[test]
+    fn load_without_backup_keeps_the_broken_file() {
+        let dir = temp_dir("broken");
+        let path = dir.join("data.toml");
+
+        fs::write(&path, "groups = 3").unwrap();
+
+        let (db, warning) = Database::load(&path);
+
+        assert!(db.groups.is_empty());
+        assert!(warning.unwrap().contains("no backup"));
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,453 @@
+use std::{
+    fs, io,
+    path::Path,
+    sync::{mpsc, Mutex, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use notify::{RecursiveMode, Watcher};
+use tauri::{AppHandle, Emitter};
+
+use super::{get_database, parse, timestamp, with_suffix, Character, Database};
+use crate::{desktop, error::CommandError, profile};
+
+/// The database as it was last read from or written to the disk, changes
+/// made since then by another program are merged against it
+static BASE: Mutex<Option<Database>> = Mutex::new(None);
+
+static APP: OnceLock<AppHandle> = OnceLock::new();
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The file did not change since it was last read or written
+    Unchanged,
+    /// The database was replaced by the file
+    Reloaded,
+    /// Both the database and the file changed, the database holds the merge
+    /// and still has to be written
+    Merged,
+}
+
+/// Reads the database at `path`, `None` when there is no file yet
+pub fn read(path: &Path) -> Result<Option<Database>, CommandError> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(Some(parse(&data)?.0)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remembers `db` as the content of the file
+pub fn set_base(db: Option<Database>) {
+    if let Ok(mut base) = BASE.lock() {
+        *base = db;
+    }
+}
+
+/// Brings the changes made to the file at `path` into `db`, fails with
+/// `CommandError::Conflict` when both changed the same thing
+pub fn sync(db: &mut Database, path: &Path) -> Result<Outcome, CommandError> {
+    let mut base = BASE.lock()?;
+    let outcome = sync_with(&mut base, db, path)?;
+
+    if outcome != Outcome::Unchanged {
+        let _ = desktop::set_matchers(&db.settings.matchers);
+    }
+
+    Ok(outcome)
+}
+
+fn sync_with(
+    base: &mut Option<Database>,
+    db: &mut Database,
+    path: &Path,
+) -> Result<Outcome, CommandError> {
+    // A deleted file is written again by the next save
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Outcome::Unchanged),
+        Err(e) => return Err(e.into()),
+    };
+
+    // There is nothing to merge in a file that cannot be parsed, it is kept
+    // aside so that the next save does not lose the edit
+    let (disk, migrated) = match parse(&data) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let broken = with_suffix(path, &format!(".broken-{}", timestamp()));
+            fs::rename(path, &broken)?;
+
+            report(CommandError::Io(format!(
+                "{} was edited elsewhere but is invalid ({e}), it was moved to {}",
+                path.display(),
+                broken.display()
+            )));
+
+            return Ok(Outcome::Unchanged);
+        }
+    };
+
+    if base.as_ref() == Some(&disk) {
+        return Ok(Outcome::Unchanged);
+    }
+
+    let empty = Database::default();
+    let merged = merge(base.as_ref().unwrap_or(&empty), db, &disk)
+        .map_err(|fields| CommandError::Conflict(fields.join(", ")))?;
+
+    // Migrated files get new ids on every read until they are written
+    let outcome = if merged == disk && !migrated {
+        Outcome::Reloaded
+    } else {
+        Outcome::Merged
+    };
+
+    *db = merged;
+    *base = Some(disk);
+
+    Ok(outcome)
+}
+
+/// Takes the side that changed from `base`, returns the conflicting fields
+/// when both did
+fn merge_field<T: PartialEq + Clone>(
+    name: &str,
+    base: &T,
+    ours: &T,
+    theirs: &T,
+    conflicts: &mut Vec<String>,
+) -> T {
+    if ours == theirs || ours == base {
+        theirs.clone()
+    } else if theirs == base {
+        ours.clone()
+    } else {
+        conflicts.push(name.to_owned());
+        ours.clone()
+    }
+}
+
+/// Three way merge of the databases, characters are merged one by one and the
+/// other fields as a whole
+pub fn merge(base: &Database, ours: &Database, theirs: &Database) -> Result<Database, Vec<String>> {
+    let mut conflicts = vec![];
+
+    // Their order first, so that a file edited on one side only reloads as is
+    let mut keys = theirs
+        .characters
+        .iter()
+        .map(Character::key)
+        .collect::<Vec<_>>();
+
+    for character in ours.characters.iter() {
+        if theirs.character(&character.key()).is_none() {
+            keys.push(character.key());
+        }
+    }
+
+    let mut characters = vec![];
+
+    for key in keys {
+        let character = merge_field(
+            &format!("character {key}"),
+            &base.character(&key),
+            &ours.character(&key),
+            &theirs.character(&key),
+            &mut conflicts,
+        );
+
+        if let Some(character) = character {
+            characters.push(character.clone());
+        }
+    }
+
+    let merged = Database {
+        schema_version: theirs.schema_version,
+        groups: merge_field(
+            "groups",
+            &base.groups,
+            &ours.groups,
+            &theirs.groups,
+            &mut conflicts,
+        ),
+        characters,
+        keymap: merge_field(
+            "keymap",
+            &base.keymap,
+            &ours.keymap,
+            &theirs.keymap,
+            &mut conflicts,
+        ),
+        macros: merge_field(
+            "macros",
+            &base.macros,
+            &ours.macros,
+            &theirs.macros,
+            &mut conflicts,
+        ),
+        settings: merge_field(
+            "settings",
+            &base.settings,
+            &ours.settings,
+            &theirs.settings,
+            &mut conflicts,
+        ),
+        active_group: merge_field(
+            "active group",
+            &base.active_group,
+            &ours.active_group,
+            &theirs.active_group,
+            &mut conflicts,
+        ),
+    };
+
+    if conflicts.is_empty() {
+        Ok(merged)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// Reloads the database when its file is changed by another program, the
+/// frontend is told through the `database-changed` and `database-conflict`
+/// events
+pub fn watch(app: AppHandle) {
+    let _ = APP.set(app.clone());
+
+    thread::spawn(move || {
+        let (tx, rx) = mpsc::channel();
+
+        let Ok(mut watcher) = notify::recommended_watcher(tx) else {
+            return;
+        };
+
+        let dir = profile::get_data_dir();
+
+        // The file is replaced on save, so the directory is watched instead
+        if fs::create_dir_all(dir).is_err() || watcher.watch(dir, RecursiveMode::Recursive).is_err()
+        {
+            return;
+        }
+
+        while let Ok(event) = rx.recv() {
+            let path = profile::get_profile_path();
+
+            let Ok(event) = event else {
+                continue;
+            };
+
+            if !event.paths.contains(&path) {
+                continue;
+            }
+
+            // Editors write in several steps
+            thread::sleep(Duration::from_millis(100));
+            while rx.try_recv().is_ok() {}
+
+            reload(&app, &path);
+        }
+    });
+}
+
+fn reload(app: &AppHandle, path: &Path) {
+    // The next save moves an invalid file aside
+    if let Err(e) = read(path) {
+        report(e);
+        return;
+    }
+
+    let result = get_database()
+        .write()
+        .map_err(CommandError::from)
+        .and_then(|mut db| match sync(&mut db, path)? {
+            Outcome::Merged => db.save().map(|_| true),
+            Outcome::Reloaded => Ok(true),
+            Outcome::Unchanged => Ok(false),
+        });
+
+    match result {
+        Ok(false) => {}
+        Ok(true) => {
+            #[cfg(desktop)]
+            crate::tray::refresh();
+
+            let _ = app.emit("database-changed", ());
+        }
+        Err(e) => report(e),
+    }
+}
+
+/// Tells the frontend about a failure of a change it did not ask for, e.g.
+/// from the tray, a hotkey or a window, through the `database-conflict` and
+/// `database-error` events
+pub fn report(e: CommandError) {
+    eprintln!("{e}");
+
+    let Some(app) = APP.get() else {
+        return;
+    };
+
+    let event = match e {
+        CommandError::Conflict(_) => "database-conflict",
+        _ => "database-error",
+    };
+
+    let _ = app.emit(event, e);
+}
+
+/// Settles a conflict by writing the database over the file when
+/// `keep_local` is set, whatever the file holds, or by loading the file
+/// otherwise
+#[tauri::command]
+pub fn resolve_conflict(app: AppHandle, keep_local: bool) -> Result<(), CommandError> {
+    let path = profile::get_profile_path();
+
+    {
+        let mut db = get_database().write()?;
+
+        if keep_local {
+            db.save_to(&path)?;
+            set_base(Some(db.clone()));
+        } else {
+            let disk = read(&path)?.ok_or_else(|| CommandError::not_found(path.display()))?;
+
+            *db = disk;
+            set_base(Some(db.clone()));
+            let _ = desktop::set_matchers(&db.settings.matchers);
+        }
+    }
+
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    let _ = app.emit("database-changed", ());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            tests::{database, temp_dir},
+            Group,
+        },
+        desktop::FocusStrategy,
+    };
+
+    #[test]
+    fn merge_changes_from_both_sides() {
+        let base = database("duo");
+
+        let mut ours = base.clone();
+        ours.settings.focus_timeout = 200;
+        ours.characters.push(Character::new("Iop"));
+
+        let mut theirs = base.clone();
+        theirs.groups.push(Group::new("trio"));
+        theirs.characters.push(Character::new("Cra"));
+
+        let merged = merge(&base, &ours, &theirs).unwrap();
+
+        assert_eq!(merged.settings.focus_timeout, 200);
+        assert_eq!(merged.groups, theirs.groups);
+        assert!(merged.character(&"Iop".into()).is_some());
+        assert!(merged.character(&"Cra".into()).is_some());
+    }
+
+    #[test]
+    fn merge_detects_conflicts() {
+        let base = database("duo");
+
+        let mut ours = base.clone();
+        ours.settings.focus_strategy = FocusStrategy::Ewmh;
+        ours.groups[0].name = "ours".to_owned();
+
+        let mut theirs = base.clone();
+        theirs.settings.focus_strategy = FocusStrategy::InputFocus;
+        theirs.groups[0].name = "ours".to_owned();
+
+        assert_eq!(
+            merge(&base, &ours, &theirs),
+            Err(vec!["settings".to_owned()])
+        );
+    }
+
+    #[test]
+    fn sync_reloads_external_edits() {
+        let path = temp_dir("sync").join("data.toml");
+        let mut db = database("duo");
+        db.save_to(&path).unwrap();
+
+        let mut base = Some(db.clone());
+        assert_eq!(
+            sync_with(&mut base, &mut db, &path).unwrap(),
+            Outcome::Unchanged
+        );
+
+        let mut edited = db.clone();
+        edited.groups[0].name = "edited".to_owned();
+        edited.save_to(&path).unwrap();
+
+        assert_eq!(
+            sync_with(&mut base, &mut db, &path).unwrap(),
+            Outcome::Reloaded
+        );
+        assert_eq!(db, edited);
+        assert_eq!(base, Some(edited));
+    }
+
+    #[test]
+    fn sync_keeps_conflicting_edits() {
+        let path = temp_dir("conflict").join("data.toml");
+        let mut db = database("duo");
+        db.save_to(&path).unwrap();
+
+        let mut base = Some(db.clone());
+
+        let mut edited = db.clone();
+        edited.groups[0].name = "edited".to_owned();
+        edited.save_to(&path).unwrap();
+
+        db.groups[0].name = "local".to_owned();
+        let local = db.clone();
+
+        assert!(matches!(
+            sync_with(&mut base, &mut db, &path),
+            Err(CommandError::Conflict(_))
+        ));
+        assert_eq!(db, local);
+        assert_eq!(read(&path).unwrap(), Some(edited));
+
+        // The conflict persists until it is resolved
+        assert!(sync_with(&mut base, &mut db, &path).is_err());
+    }
+
+    #[test]
+    fn sync_moves_invalid_files_aside() {
+        let dir = temp_dir("invalid");
+        let path = dir.join("data.toml");
+        let before = database("duo");
+        let mut db = before.clone();
+        let mut base = Some(before.clone());
+
+        fs::write(&path, "groups = [").unwrap();
+
+        assert_eq!(
+            sync_with(&mut base, &mut db, &path).unwrap(),
+            Outcome::Unchanged
+        );
+        assert_eq!(db, before);
+        assert_eq!(base, Some(before));
+        assert!(!path.exists());
+
+        let broken = fs::read_dir(&dir)
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|entry| entry.file_name().to_string_lossy().contains(".broken-"))
+            .unwrap();
+        assert_eq!(fs::read_to_string(broken.path()).unwrap(), "groups = [");
+    }
+}
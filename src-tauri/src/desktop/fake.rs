@@ -0,0 +1,284 @@
+use anyhow::{anyhow, bail, Result};
+use std::{
+    collections::HashSet,
+    fs,
+    sync::{atomic::AtomicBool, Arc, Condvar, Mutex, MutexGuard, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use super::{Desktop, FocusError, FocusStrategy, WindowEvent, WindowMatch, Windows};
+use crate::database::CharacterKey;
+
+#[derive(Debug, Default)]
+struct FakeState {
+    windows: Vec<(u32, CharacterKey)>,
+    active_window: Option<u32>,
+    shown_windows: Vec<u32>,
+    refused_windows: HashSet<u32>,
+    events: Vec<WindowEvent>,
+    next_id: u32,
+}
+
+/// In-memory desktop used by tests and headless runs, every clone shares the
+/// same windows.
+#[derive(Clone, Debug, Default)]
+pub struct FakeDesktop {
+    state: Arc<Mutex<FakeState>>,
+    changed: Arc<Condvar>,
+}
+
+static SHARED: OnceLock<FakeDesktop> = OnceLock::new();
+
+impl FakeDesktop {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The instance returned by `connect`, shared by the whole process. It
+    /// plays the script at `DOFUS_SWITCHER_FAKE_SCRIPT` when it is set, see
+    /// `run_script`
+    pub fn shared() -> &'static FakeDesktop {
+        SHARED.get_or_init(|| {
+            let desktop = FakeDesktop::new();
+
+            if let Some(path) = std::env::var_os("DOFUS_SWITCHER_FAKE_SCRIPT") {
+                let desktop = desktop.clone();
+
+                thread::spawn(move || {
+                    let result = fs::read_to_string(&path)
+                        .map_err(Into::into)
+                        .and_then(|script| desktop.run_script(&script));
+
+                    if let Err(e) = result {
+                        eprintln!("fake desktop script {}: {e}", path.to_string_lossy());
+                    }
+                });
+            }
+
+            desktop
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Scripting, driven by the tests and by `run_script` in headless runs
+impl FakeDesktop {
+    fn push_event(&self, state: &mut FakeState, event: WindowEvent) {
+        state.events.push(event);
+        self.changed.notify_all();
+    }
+
+    /// Opens a window showing the character and returns its id
+    pub fn open_window(&self, character: impl Into<CharacterKey>) -> u32 {
+        let mut state = self.state();
+
+        state.next_id += 1;
+        let id = state.next_id;
+        let character = character.into();
+        state.windows.push((id, character.clone()));
+        self.push_event(&mut state, WindowEvent::Updated { id, character });
+
+        id
+    }
+
+    pub fn close_window(&self, id: u32) {
+        let mut state = self.state();
+
+        state.windows.retain(|(win, _)| win != &id);
+
+        if state.active_window == Some(id) {
+            state.active_window = None;
+        }
+
+        self.push_event(&mut state, WindowEvent::Removed { id });
+    }
+
+    pub fn rename_window(&self, id: u32, character: impl Into<CharacterKey>) {
+        let character = character.into();
+        let mut state = self.state();
+
+        let Some((_, current)) = state.windows.iter_mut().find(|(win, _)| win == &id) else {
+            return;
+        };
+
+        current.clone_from(&character);
+        self.push_event(&mut state, WindowEvent::Updated { id, character });
+    }
+
+    /// The first window showing a character with this name
+    pub fn find_window(&self, name: &str) -> Option<u32> {
+        self.state()
+            .windows
+            .iter()
+            .find(|(_, character)| character.name == name)
+            .map(|(id, _)| id.to_owned())
+    }
+
+    /// Simulates the user focusing a window, it is not recorded by
+    /// `shown_windows`
+    pub fn focus_window(&self, id: Option<u32>) {
+        self.state().active_window = id;
+    }
+
+    /// Makes `show_window` fail with `FocusError::Refused` for this window
+    pub fn refuse_focus(&self, id: u32) {
+        self.state().refused_windows.insert(id);
+    }
+
+    /// Every window passed to `show_window`, in call order
+    pub fn shown_windows(&self) -> Vec<u32> {
+        self.state().shown_windows.clone()
+    }
+
+    /// Plays one command per line, windows are named after the character
+    /// they show:
+    ///
+    /// - `open <name> [server]`
+    /// - `close <name>`
+    /// - `rename <name> <new name> [server]`
+    /// - `focus <name>`, or `focus` alone to focus nothing
+    /// - `refuse <name>`
+    /// - `sleep <milliseconds>`
+    /// - `shown`, prints the windows passed to `show_window` so far
+    ///
+    /// Empty lines and lines starting with `#` are skipped.
+    pub fn run_script(&self, script: &str) -> Result<()> {
+        for (n, line) in script.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            self.run_line(line)
+                .map_err(|e| anyhow!("line {}: {e}", n + 1))?;
+        }
+
+        Ok(())
+    }
+
+    fn run_line(&self, line: &str) -> Result<()> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let args = words.collect::<Vec<_>>();
+
+        let window = |name: &str| {
+            self.find_window(name)
+                .ok_or_else(|| anyhow!("no window shows {name}"))
+        };
+        let key = |name: &str, server: Option<&&str>| {
+            CharacterKey::new(name, server.map(|s| s.to_string()))
+        };
+
+        match (command, args.as_slice()) {
+            ("open", [name, server @ ..]) if server.len() <= 1 => {
+                self.open_window(key(name, server.first()));
+            }
+            ("close", [name]) => self.close_window(window(name)?),
+            ("rename", [name, new_name, server @ ..]) if server.len() <= 1 => {
+                self.rename_window(window(name)?, key(new_name, server.first()));
+            }
+            ("focus", []) => self.focus_window(None),
+            ("focus", [name]) => self.focus_window(Some(window(name)?)),
+            ("refuse", [name]) => self.refuse_focus(window(name)?),
+            ("sleep", [ms]) => thread::sleep(Duration::from_millis(ms.parse()?)),
+            ("shown", []) => println!("shown windows: {:?}", self.shown_windows()),
+            _ => bail!("invalid command: {line}"),
+        }
+
+        Ok(())
+    }
+}
+
+impl Desktop for FakeDesktop {
+    fn connect() -> Result<Self> {
+        Ok(Self::shared().clone())
+    }
+
+    fn show_window(
+        &self,
+        id: u32,
+        _strategy: FocusStrategy,
+        _timeout: Duration,
+        _cancelled: &AtomicBool,
+    ) -> Result<(), FocusError> {
+        let mut state = self.state();
+
+        if !state.windows.iter().any(|(win, _)| win == &id) {
+            return Err(FocusError::WindowGone);
+        }
+
+        if state.refused_windows.contains(&id) {
+            return Err(FocusError::Refused);
+        }
+
+        state.active_window = Some(id);
+        state.shown_windows.push(id);
+
+        Ok(())
+    }
+
+    fn get_active_window(&self) -> Result<Option<u32>> {
+        Ok(self.state().active_window)
+    }
+
+    fn get_windows(&self) -> Result<Windows> {
+        Ok(self.state().windows.iter().cloned().collect())
+    }
+
+    /// Fake windows only tell their character
+    fn read_title(&self, id: u32) -> Result<Option<WindowMatch>> {
+        Ok(self
+            .state()
+            .windows
+            .iter()
+            .find(|(win, _)| *win == id)
+            .map(|(_, character)| WindowMatch {
+                server: character.server.clone(),
+                ..WindowMatch::new(character.name.to_owned())
+            }))
+    }
+
+    fn wait_for_changes(&self) -> Result<Vec<WindowEvent>> {
+        let mut state = self.state();
+
+        while state.events.is_empty() {
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+
+        Ok(std::mem::take(&mut state.events))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_scripts() {
+        let desktop = FakeDesktop::new();
+
+        desktop
+            .run_script(
+                "# Two accounts
+                open Iop-Leader
+                open Cra-Alt Imagiro
+
+                rename Iop-Leader Iop-Two
+                focus Iop-Two
+                close Cra-Alt",
+            )
+            .unwrap();
+
+        let id = desktop.find_window("Iop-Two").unwrap();
+        assert_eq!(desktop.get_windows().unwrap().len(), 1);
+        assert_eq!(desktop.get_active_window().unwrap(), Some(id));
+
+        assert!(desktop.run_script("close Cra-Alt").is_err());
+        assert!(desktop.run_script("open").is_err());
+    }
+}
@@ -0,0 +1,346 @@
+use lazy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    OnceLock, RwLock, RwLockReadGuard,
+};
+
+use crate::{
+    database::{Breed, CharacterKey},
+    error::CommandError,
+};
+
+/// Tells Dofus windows apart and reads the character from their title
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Matcher {
+    /// Regex matched against both the instance and the class of `WM_CLASS`
+    pub class: String,
+    /// Regex matched against `_NET_WM_NAME`, it captures the character name
+    /// as `character` and optionally `server`, `breed` and `level`
+    pub title: String,
+}
+
+impl Matcher {
+    fn new(class: &str, title: &str) -> Self {
+        Self {
+            class: class.to_owned(),
+            title: title.to_owned(),
+        }
+    }
+}
+
+/// Dofus 2 and 3 through Wine or natively, then Dofus Retro. Titles without
+/// ` - `, like the login screen's, do not show a character, the server is
+/// read when it follows the name in parentheses. Proton names the
+/// class after the Steam id of the shortcut, which differs on every machine,
+/// so it takes a custom matcher
+pub fn default_matchers() -> Vec<Matcher> {
+    vec![
+        Matcher::new(
+            r"(?i)^(dofus retro|dofusretro(\.exe)?)$",
+            r"^(?P<character>\S+)(?: \((?P<server>[^)]+)\))? - Dofus Retro",
+        ),
+        Matcher::new(
+            r"(?i)^dofus(\.exe)?$",
+            r"^(?P<character>\S+)(?: \((?P<server>[^)]+)\))? - (?:(?P<breed>[^\s\d-][^-]*?) - )?(?:(?:Niveau|Level) (?P<level>\d+) - )?",
+        ),
+    ]
+}
+
+/// `WM_CLASS` split into its two null terminated strings
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WmClass {
+    pub instance: String,
+    pub class: String,
+}
+
+impl WmClass {
+    pub fn parse(value: &[u8]) -> Self {
+        let mut parts = value
+            .split(|b| *b == 0)
+            .map(|part| String::from_utf8_lossy(part).into_owned());
+
+        Self {
+            instance: parts.next().unwrap_or_default(),
+            class: parts.next().unwrap_or_default(),
+        }
+    }
+}
+
+/// What a matcher read from a window title, captures that cannot be parsed
+/// are left out
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct WindowMatch {
+    pub character: String,
+    pub server: Option<String>,
+    pub breed: Option<Breed>,
+    pub level: Option<u32>,
+}
+
+impl WindowMatch {
+    pub fn new(character: impl Into<String>) -> Self {
+        Self {
+            character: character.into(),
+            server: None,
+            breed: None,
+            level: None,
+        }
+    }
+
+    pub fn key(&self) -> CharacterKey {
+        CharacterKey::new(self.character.to_owned(), self.server.clone())
+    }
+}
+
+/// Compiled matchers, the first one matching a window wins
+#[derive(Clone, Debug)]
+pub struct Rules(Vec<(Regex, Regex)>);
+
+impl Rules {
+    pub fn new(matchers: &[Matcher]) -> Result<Self, CommandError> {
+        let compile = |regex: &str| {
+            Regex::new(regex)
+                .map_err(|e| CommandError::Invalid(format!("invalid window matcher: {e}")))
+        };
+
+        let rules = matchers
+            .iter()
+            .map(|matcher| {
+                let title = compile(&matcher.title)?;
+
+                if !title.capture_names().any(|n| n == Some("character")) {
+                    return Err(CommandError::Invalid(format!(
+                        "window matcher \"{}\" does not capture the character",
+                        matcher.title
+                    )));
+                }
+
+                Ok((compile(&matcher.class)?, title))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self(rules))
+    }
+
+    /// Whether the window belongs to a Dofus client
+    pub fn is_dofus(&self, class: &WmClass) -> bool {
+        self.0.iter().any(|(regex, _)| matches_class(regex, class))
+    }
+
+    /// The character shown by the window, `None` for other windows and
+    /// titles without a character
+    pub fn find(&self, class: &WmClass, title: &str) -> Option<WindowMatch> {
+        self.0
+            .iter()
+            .filter(|(regex, _)| matches_class(regex, class))
+            .find_map(|(_, regex)| {
+                let captures = regex.captures(title)?;
+                let capture = |name| {
+                    captures
+                        .name(name)
+                        .map(|m| m.as_str().trim().to_owned())
+                        .filter(|s| !s.is_empty())
+                };
+
+                Some(WindowMatch {
+                    character: capture("character")?,
+                    server: capture("server"),
+                    breed: capture("breed").and_then(|b| Breed::parse(&b)),
+                    level: capture("level").and_then(|l| l.parse().ok()),
+                })
+            })
+    }
+}
+
+fn matches_class(regex: &Regex, class: &WmClass) -> bool {
+    regex.is_match(&class.instance) || regex.is_match(&class.class)
+}
+
+static RULES: OnceLock<RwLock<Rules>> = OnceLock::new();
+
+/// Bumped by `set_matchers` so that the backends check their windows again
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn rules() -> &'static RwLock<Rules> {
+    RULES.get_or_init(|| {
+        RwLock::new(Rules::new(&default_matchers()).expect("default matchers are valid"))
+    })
+}
+
+/// Rules used by the desktop backends, the defaults until `set_matchers`
+pub fn get_rules() -> RwLockReadGuard<'static, Rules> {
+    rules().read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Changes of the rules so far, see `set_matchers`
+pub fn get_generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// The custom matchers followed by the default ones
+fn with_defaults(matchers: &[Matcher]) -> Vec<Matcher> {
+    matchers.iter().cloned().chain(default_matchers()).collect()
+}
+
+/// Fails on the matchers that `set_matchers` would refuse
+pub fn validate_matchers(matchers: &[Matcher]) -> Result<(), CommandError> {
+    Rules::new(matchers).map(|_| ())
+}
+
+/// Replaces the custom rules, tried before the default ones, the backends
+/// check the windows they track again
+pub fn set_matchers(matchers: &[Matcher]) -> Result<(), CommandError> {
+    let updated = Rules::new(&with_defaults(matchers))?;
+
+    *rules().write()? = updated;
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+
+    Ok(())
+}
+
+/// Runs `matchers` and the default ones on a window, to try them before
+/// saving
+#[tauri::command]
+pub fn match_window(
+    matchers: Vec<Matcher>,
+    class: String,
+    title: String,
+) -> Result<Option<WindowMatch>, CommandError> {
+    let class = WmClass {
+        instance: class.to_owned(),
+        class,
+    };
+
+    Ok(Rules::new(&with_defaults(&matchers))?.find(&class, &title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(name: &str) -> WmClass {
+        WmClass {
+            instance: name.to_lowercase(),
+            class: name.to_owned(),
+        }
+    }
+
+    fn find(class_name: &str, title: &str) -> Option<WindowMatch> {
+        Rules::new(&default_matchers())
+            .unwrap()
+            .find(&class(class_name), title)
+    }
+
+    fn character(class_name: &str, title: &str) -> Option<String> {
+        find(class_name, title).map(|m| m.character)
+    }
+
+    #[test]
+    fn parse_wm_class() {
+        assert_eq!(
+            WmClass::parse(b"dofus.exe\0Dofus.exe\0"),
+            WmClass {
+                instance: "dofus.exe".to_owned(),
+                class: "Dofus.exe".to_owned(),
+            }
+        );
+        assert_eq!(WmClass::parse(b"").instance, "");
+    }
+
+    #[test]
+    fn default_matchers_find_characters() {
+        assert_eq!(
+            character("dofus.exe", "Iop-Leader - Dofus 2.71.5.15"),
+            Some("Iop-Leader".to_owned())
+        );
+        assert_eq!(
+            character("Dofus", "Iop-Leader - Iop - 3.0.12.7 - Release"),
+            Some("Iop-Leader".to_owned())
+        );
+        assert_eq!(
+            character("Dofus Retro", "Eni-Heal - Dofus Retro v1.44"),
+            Some("Eni-Heal".to_owned())
+        );
+
+        // Login screens and other programs
+        assert_eq!(character("dofus.exe", "Dofus"), None);
+        assert_eq!(character("Dofus Retro", "Dofus Retro"), None);
+        assert_eq!(character("dofus-switcher", "Iop - Dofus 2.71"), None);
+        assert_eq!(character("steam_app_1234", "Cra-Alt - Crâ - 3.0"), None);
+    }
+
+    #[test]
+    fn custom_matchers_come_first() {
+        let rules = Rules::new(&with_defaults(&[Matcher::new(
+            "^(steam_app_1234|Dofus)$",
+            r"^(?P<character>\S+) \| ",
+        )]))
+        .unwrap();
+
+        assert_eq!(
+            rules
+                .find(&class("steam_app_1234"), "Cra-Alt | Crâ")
+                .map(|m| m.character),
+            Some("Cra-Alt".to_owned())
+        );
+        assert_eq!(
+            rules
+                .find(&class("Dofus"), "Iop-Leader - Iop - 3.0")
+                .map(|m| m.character),
+            Some("Iop-Leader".to_owned())
+        );
+        assert!(!rules.is_dofus(&class("steam_app_5678")));
+    }
+
+    #[test]
+    fn captures_server_and_breed() {
+        let rules = Rules::new(&[Matcher::new(
+            "^Dofus$",
+            r"^\[(?P<server>\w+)\] (?P<character>\S+) \((?P<breed>\w+) (?P<level>\d+)\)",
+        )])
+        .unwrap();
+
+        assert_eq!(
+            rules.find(&class("Dofus"), "[Imagiro] Iop-Leader (Iop 200)"),
+            Some(WindowMatch {
+                character: "Iop-Leader".to_owned(),
+                server: Some("Imagiro".to_owned()),
+                breed: Some(Breed::Iop),
+                level: Some(200),
+            })
+        );
+        assert_eq!(
+            find("Dofus", "Iop-Leader - Iop - 3.0.12.7 - Release")
+                .unwrap()
+                .breed,
+            Some(Breed::Iop)
+        );
+        assert_eq!(
+            find("dofus.exe", "Cra-Alt - Crâ - Niveau 87 - 2.71").unwrap(),
+            WindowMatch {
+                breed: Some(Breed::Cra),
+                level: Some(87),
+                ..WindowMatch::new("Cra-Alt")
+            }
+        );
+        assert_eq!(
+            find("Dofus", "Iop-Leader (Imagiro) - Iop - 3.0").unwrap(),
+            WindowMatch {
+                server: Some("Imagiro".to_owned()),
+                breed: Some(Breed::Iop),
+                ..WindowMatch::new("Iop-Leader")
+            }
+        );
+        // Unknown breeds are ignored
+        assert_eq!(
+            find("Dofus", "Iop-Leader - Dofus - 2.71").unwrap().breed,
+            None
+        );
+    }
+
+    #[test]
+    fn reject_invalid_matchers() {
+        assert!(Rules::new(&[Matcher::new("(", "(?P<character>.+)")]).is_err());
+        assert!(Rules::new(&[Matcher::new("dofus", "^(?P<name>.+)$")]).is_err());
+    }
+}
@@ -1,100 +1,671 @@
-use crate::database::{get_database, Character};
+use crate::database::{
+    get_database, report, timestamp, Character, CharacterKey, Database, Group, Member,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    sync::{OnceLock, RwLock},
-    thread::{self, sleep},
+    collections::{BTreeMap, HashSet},
+    fmt,
+    sync::{atomic::AtomicBool, OnceLock, RwLock},
+    thread,
     time::Duration,
 };
+use uuid::Uuid;
 
+mod fake;
+mod matcher;
 mod x11;
 
-pub use x11::X11Desktop as Desktop;
+pub use fake::FakeDesktop;
+pub use matcher::{match_window, set_matchers, validate_matchers, Matcher, WindowMatch};
+pub use x11::X11Desktop;
+
+pub trait Desktop {
+    fn connect() -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Focuses, raises and restores the window, waiting up to `timeout` for
+    /// the window manager to confirm it or for `cancelled` to be set
+    fn show_window(
+        &self,
+        id: u32,
+        strategy: FocusStrategy,
+        timeout: Duration,
+        cancelled: &AtomicBool,
+    ) -> Result<(), FocusError>;
+
+    /// The focused window if it belongs to a Dofus client
+    fn get_active_window(&self) -> Result<Option<u32>>;
+
+    /// Dofus windows with the character they show
+    fn get_windows(&self) -> Result<Windows>;
+
+    /// What the title of the window tells about its character
+    fn read_title(&self, id: u32) -> Result<Option<WindowMatch>>;
+
+    /// Blocks until Dofus windows are opened, renamed or closed
+    fn wait_for_changes(&self) -> Result<Vec<WindowEvent>>;
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FocusStrategy {
+    /// `Ewmh` when the window manager supports `_NET_ACTIVE_WINDOW`,
+    /// `InputFocus` otherwise
+    #[default]
+    Auto,
+    /// Ask the window manager with a `_NET_ACTIVE_WINDOW` client message
+    Ewmh,
+    /// Set the input focus and raise the window ourselves
+    InputFocus,
+}
+
+#[derive(Debug)]
+pub enum FocusError {
+    /// The window was closed before or while focusing it
+    WindowGone,
+    /// The window manager focused another window instead
+    Refused,
+    /// No focus change was seen before the timeout
+    Timeout,
+    /// The hotkey was cancelled while waiting
+    Cancelled,
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for FocusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FocusError::WindowGone => write!(f, "window no longer exists"),
+            FocusError::Refused => write!(f, "window manager refused to focus the window"),
+            FocusError::Timeout => write!(f, "timed out waiting for the window to be focused"),
+            FocusError::Cancelled => write!(f, "cancelled while focusing the window"),
+            FocusError::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for FocusError {}
+
+impl From<anyhow::Error> for FocusError {
+    fn from(e: anyhow::Error) -> Self {
+        e.downcast::<FocusError>().unwrap_or_else(FocusError::Other)
+    }
+}
+
+/// Dofus windows by id, several windows may show characters with the same
+/// key, for instance when the title does not tell the server
+pub type Windows = BTreeMap<u32, CharacterKey>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WindowEvent {
+    /// A Dofus window appeared or its character changed
+    Updated { id: u32, character: CharacterKey },
+    /// A Dofus window was closed or no longer shows a character
+    Removed { id: u32 },
+}
+
+impl WindowEvent {
+    pub fn apply(&self, windows: &mut Windows) {
+        match self {
+            WindowEvent::Updated { id, character } => {
+                windows.insert(*id, character.clone());
+            }
+            WindowEvent::Removed { id } => {
+                windows.remove(id);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    X11,
+    Fake,
+}
+
+impl Backend {
+    /// Reads `DOFUS_SWITCHER_DESKTOP`, defaulting to X11. The fake desktop
+    /// starts empty and plays `DOFUS_SWITCHER_FAKE_SCRIPT` when it is set
+    pub fn from_env() -> Self {
+        match std::env::var("DOFUS_SWITCHER_DESKTOP").as_deref() {
+            Ok("fake") => Backend::Fake,
+            _ => Backend::X11,
+        }
+    }
+}
+
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+/// Selects the backend used by `connect`, only the first call has an effect
+pub fn set_backend(backend: Backend) {
+    let _ = BACKEND.set(backend);
+}
+
+pub fn get_backend() -> Backend {
+    *BACKEND.get_or_init(Backend::from_env)
+}
+
+pub fn connect() -> Result<Box<dyn Desktop>> {
+    match get_backend() {
+        Backend::X11 => Ok(Box::new(X11Desktop::connect()?)),
+        Backend::Fake => Ok(Box::new(FakeDesktop::connect()?)),
+    }
+}
+
+static WINDOWS: OnceLock<RwLock<Windows>> = OnceLock::new();
 
-static CHARACTER_WINDOWS: OnceLock<RwLock<Option<Vec<(u32, Character)>>>> = OnceLock::new();
+/// Dofus windows kept up to date by `watch`
+pub fn get_windows() -> &'static RwLock<Windows> {
+    WINDOWS.get_or_init(Default::default)
+}
+
+/// What the hotkeys act on, read from the active group at once
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ActiveMembers {
+    /// Online and enabled characters with their window, see `group_windows`
+    pub windows: Vec<(u32, Character)>,
+    pub slots: Vec<Slot>,
+    /// Character designated by `Group::leader`, online or not
+    pub leader: Option<Uuid>,
+}
+
+pub fn get_active_members() -> Option<ActiveMembers> {
+    let windows = get_windows().read().ok()?;
+    let db = get_database().read().ok()?;
+
+    get_active_group(&db, &windows).map(|group| ActiveMembers {
+        windows: group_windows(&db, group, &windows),
+        slots: group_slots(&db, group, &windows),
+        leader: group.leader(),
+    })
+}
+
+/// The group selected by the user, or the one picked by `pick_group` in auto
+/// mode
+pub fn get_active_group<'a>(db: &'a Database, windows: &Windows) -> Option<&'a Group> {
+    if db.settings.auto_group {
+        pick_group(db, windows)
+    } else {
+        db.active_group()
+    }
+}
+
+/// Picks the group with the highest percentage of its characters online, the
+/// last one wins ties
+pub fn pick_group<'a>(db: &'a Database, windows: &Windows) -> Option<&'a Group> {
+    db.groups.iter().max_by_key(|group| {
+        let count = db
+            .members(group)
+            .filter(|(_, c)| windows.values().any(|key| c.has_key(key)))
+            .count();
+
+        if group.members.is_empty() {
+            0
+        } else {
+            count * 100 / group.members.len()
+        }
+    })
+}
+
+/// Enabled members of the group with their windows, oldest first. A window
+/// matching several members, e.g. one whose title shows no server, goes to
+/// the first of them still without a window so that each one can be reached
+fn member_windows<'a>(
+    db: &'a Database,
+    group: &'a Group,
+    windows: &Windows,
+) -> Vec<(&'a Member, &'a Character, Vec<u32>)> {
+    let mut members = db
+        .members(group)
+        .filter(|(member, _)| member.enabled)
+        .map(|(member, c)| {
+            let exact = windows
+                .iter()
+                .filter(|(_, key)| c.key() == **key)
+                .map(|(win, _)| *win)
+                .collect::<Vec<_>>();
+
+            (member, c, exact)
+        })
+        .collect::<Vec<_>>();
+
+    let mut taken = members
+        .iter()
+        .flat_map(|(_, _, wins)| wins.iter().copied())
+        .collect::<HashSet<_>>();
+
+    for (_, c, wins) in members.iter_mut().filter(|(_, _, wins)| wins.is_empty()) {
+        if let Some((win, _)) = windows
+            .iter()
+            .find(|(win, key)| !taken.contains(*win) && c.has_key(key))
+        {
+            wins.push(*win);
+            taken.insert(*win);
+        }
+    }
+
+    for (win, key) in windows.iter().filter(|(win, _)| !taken.contains(*win)) {
+        if let Some((_, _, wins)) = members.iter_mut().find(|(_, c, _)| c.has_key(key)) {
+            wins.push(*win);
+            wins.sort();
+        }
+    }
 
-pub fn get_character_windows() -> &'static RwLock<Option<Vec<(u32, Character)>>> {
-    CHARACTER_WINDOWS.get_or_init(|| Default::default())
+    members
+}
+
+/// Online and enabled characters of the group with their windows, in group
+/// order then oldest window first
+pub fn group_windows(db: &Database, group: &Group, windows: &Windows) -> Vec<(u32, Character)> {
+    member_windows(db, group, windows)
+        .into_iter()
+        .flat_map(|(_, c, wins)| wins.into_iter().map(|win| (win, c.clone())))
+        .collect()
+}
+
+/// A member of a group with its oldest window, `None` while it is offline
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slot {
+    pub slot: usize,
+    pub character: Character,
+    pub window: Option<u32>,
+}
+
+/// Enabled members of the group by slot, online or not
+pub fn group_slots(db: &Database, group: &Group, windows: &Windows) -> Vec<Slot> {
+    let mut slots = member_windows(db, group, windows)
+        .into_iter()
+        .map(|(member, c, wins)| Slot {
+            slot: member.slot,
+            character: c.clone(),
+            window: wins.first().copied(),
+        })
+        .collect::<Vec<_>>();
+
+    slots.sort_by_key(|s| s.slot);
+
+    slots
 }
 
 pub fn watch() {
     thread::spawn(|| {
-        let Ok(desktop) = Desktop::connect() else {
+        let Ok(desktop) = connect() else {
+            return;
+        };
+
+        let Ok(mut windows) = desktop.get_windows() else {
             return;
         };
 
         loop {
-            sleep(Duration::from_secs(1));
+            if let Ok(mut cached) = get_windows().write() {
+                cached.clone_from(&windows);
+            }
+
+            update_characters(desktop.as_ref(), &windows);
 
-            let Ok(mut windows) = desktop.get_windows() else {
+            let Ok(events) = desktop.wait_for_changes() else {
                 break;
             };
 
-            let group_wins = {
-                let Ok(db) = get_database().read() else {
-                    continue;
-                };
-
-                let mut groups = db.groups.clone();
-
-                groups.sort_by(|a, b| {
-                    let a_count = a
-                        .characters
-                        .iter()
-                        .filter(|c| windows.contains_key(&c.name))
-                        .count();
-                    let a_percent = if a.characters.is_empty() {
-                        0
-                    } else {
-                        a_count * 100 / a.characters.len()
-                    };
-
-                    let b_count = b
-                        .characters
-                        .iter()
-                        .filter(|c| windows.contains_key(&c.name))
-                        .count();
-                    let b_percent = if b.characters.is_empty() {
-                        0
-                    } else {
-                        b_count * 100 / b.characters.len()
-                    };
-
-                    a_percent.cmp(&b_percent)
-                });
-
-                groups.last().cloned().map(|g| {
-                    g.characters
-                        .iter()
-                        .filter_map(|c| windows.get(&c.name).map(|win| (win.to_owned(), c.clone())))
-                        .collect::<Vec<_>>()
-                })
-            };
+            for event in events.iter() {
+                event.apply(&mut windows);
+            }
+        }
+    });
+}
 
-            let Ok(mut character_windows) = get_character_windows().write() else {
-                continue;
-            };
+/// `last_seen` and what the title tells are only refreshed once in a while
+/// to spare the disk
+const SEEN_INTERVAL: u64 = 60 * 60;
 
-            *character_windows = group_wins;
+/// Adds the characters seen for the first time and refreshes the other ones
+fn update_characters(desktop: &dyn Desktop, windows: &Windows) {
+    let Ok(mut db) = get_database().write() else {
+        return;
+    };
 
-            let Ok(mut db) = get_database().write() else {
-                continue;
-            };
+    let changed = mark_seen(&mut db, windows, timestamp(), |id| {
+        desktop.read_title(id).ok().flatten()
+    });
 
-            for (key, _) in db.characters.iter() {
-                windows.remove(key.as_str());
-            }
+    if changed {
+        if let Err(e) = db.save() {
+            report(e);
+        }
+    }
+}
+
+/// Returns whether `db` changed. A breed set by the user is kept, the level
+/// follows the title
+fn mark_seen(
+    db: &mut Database,
+    windows: &Windows,
+    now: u64,
+    read_title: impl Fn(u32) -> Option<WindowMatch>,
+) -> bool {
+    let mut changed = false;
+
+    for (id, key) in windows {
+        let character = db.character_entry(key);
+
+        if character
+            .last_seen
+            .is_some_and(|seen| now < seen + SEEN_INTERVAL)
+        {
+            continue;
+        }
+
+        character.last_seen = Some(now);
+        changed = true;
+
+        let Some(title) = read_title(*id) else {
+            continue;
+        };
+
+        if character.breed.is_none() {
+            character.breed = title.breed;
+        }
+
+        if title.level.is_some() {
+            character.level = title.level;
+        }
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Breed, Member};
+
+    fn database(groups: &[(&str, &[&str])]) -> Database {
+        let mut db = Database::default();
 
-            if windows.is_empty() {
-                continue;
+        for (name, characters) in groups {
+            let mut group = Group::new(*name);
+
+            for character in characters.iter() {
+                let id = db.character_entry(&(*character).into()).id;
+                group.members.push(Member::new(id, group.free_slot()));
             }
 
-            let characters = windows
-                .iter()
-                .map(|(key, _)| (key.to_owned(), Character::new(key)))
-                .collect::<HashMap<_, _>>();
+            db.groups.push(group);
+        }
+
+        db
+    }
+
+    fn character<'a>(db: &'a Database, name: &str) -> &'a Character {
+        db.character(&name.into()).unwrap()
+    }
+
+    #[test]
+    fn pick_group_prefers_highest_online_percentage() {
+        let desktop = FakeDesktop::new();
+        desktop.open_window("Iop");
+        desktop.open_window("Cra");
+        let eni = desktop.open_window("Eni");
+
+        let db = database(&[
+            ("dungeon", &["Iop", "Cra", "Eni", "Sacri"]),
+            ("farm", &["Eni", "Cra"]),
+            ("empty", &[]),
+        ]);
+
+        let windows = desktop.get_windows().unwrap();
+        let wins = group_windows(&db, pick_group(&db, &windows).unwrap(), &windows);
+        let names = wins
+            .iter()
+            .map(|(_, c)| c.name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["Eni", "Cra"]);
+        assert_eq!(wins[0].0, eni);
+    }
+
+    #[test]
+    fn pick_group_follows_windows_closing() {
+        let desktop = FakeDesktop::new();
+        desktop.open_window("Iop");
+        let cra = desktop.open_window("Cra");
 
-            db.characters.extend(characters);
-            db.save();
+        let db = database(&[("solo", &["Iop"]), ("duo", &["Cra", "Eni"])]);
+
+        let windows = desktop.get_windows().unwrap();
+        let wins = group_windows(&db, pick_group(&db, &windows).unwrap(), &windows);
+        assert_eq!(wins.len(), 1);
+        assert_eq!(wins[0].1.name, "Iop");
+
+        desktop.open_window("Eni");
+        desktop.close_window(cra);
+        desktop.rename_window(desktop.find_window("Eni").unwrap(), "Iop2");
+
+        let windows = desktop.get_windows().unwrap();
+        assert_eq!(pick_group(&db, &windows).unwrap().name, "solo");
+    }
+
+    #[test]
+    fn window_events_keep_windows_in_sync() {
+        let desktop = FakeDesktop::new();
+        let iop = desktop.open_window("Iop");
+        let mut windows = desktop.get_windows().unwrap();
+
+        let cra = desktop.open_window("Cra");
+        desktop.rename_window(iop, "Iop2");
+        desktop.close_window(cra);
+
+        let events = desktop.wait_for_changes().unwrap();
+        assert_eq!(events.len(), 4);
+
+        for event in events.iter() {
+            event.apply(&mut windows);
         }
-    });
+
+        assert_eq!(windows, Windows::from([(iop, "Iop2".into())]));
+    }
+
+    #[test]
+    fn active_group_is_explicit_unless_auto() {
+        let desktop = FakeDesktop::new();
+        desktop.open_window("Cra");
+
+        let mut db = database(&[("solo", &["Iop"]), ("duo", &["Cra", "Eni"])]);
+        let windows = desktop.get_windows().unwrap();
+
+        assert_eq!(get_active_group(&db, &windows).unwrap().name, "solo");
+
+        db.active_group = Some(db.groups[1].id);
+        assert_eq!(get_active_group(&db, &windows).unwrap().name, "duo");
+
+        db.active_group = Some(uuid::Uuid::new_v4());
+        assert_eq!(get_active_group(&db, &windows).unwrap().name, "solo");
+
+        db.settings.auto_group = true;
+        assert_eq!(get_active_group(&db, &windows).unwrap().name, "duo");
+    }
+
+    #[test]
+    fn group_slots_keep_offline_members() {
+        let desktop = FakeDesktop::new();
+        let iop = desktop.open_window("Iop");
+        let eni = desktop.open_window("Eni");
+
+        let mut db = database(&[("trio", &["Iop", "Cra", "Eni", "Sram"])]);
+        db.groups[0].members[0].slot = 5;
+        db.groups[0].members[3].enabled = false;
+
+        let windows = desktop.get_windows().unwrap();
+        let slots = group_slots(&db, &db.groups[0], &windows)
+            .into_iter()
+            .map(|s| (s.slot, s.character.name, s.window))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            slots,
+            vec![
+                (2, "Cra".to_owned(), None),
+                (3, "Eni".to_owned(), Some(eni)),
+                (5, "Iop".to_owned(), Some(iop)),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_windows_skip_disabled_members() {
+        let desktop = FakeDesktop::new();
+        desktop.open_window("Iop");
+        let cra = desktop.open_window("Cra");
+
+        let mut db = database(&[("duo", &["Iop", "Cra"]), ("solo", &["Iop"])]);
+        db.groups[0].members[0].enabled = false;
+
+        let windows = desktop.get_windows().unwrap();
+        let wins = group_windows(&db, &db.groups[0], &windows);
+        assert_eq!(wins.len(), 1);
+        assert_eq!(wins[0].0, cra);
+
+        // Disabling a member only affects its group
+        assert_eq!(group_windows(&db, &db.groups[1], &windows).len(), 1);
+    }
+
+    #[test]
+    fn mark_seen_characters() {
+        let desktop = FakeDesktop::new();
+        desktop.open_window("Iop");
+        let windows = desktop.get_windows().unwrap();
+
+        let mut db = database(&[("solo", &["Iop", "Cra"])]);
+        assert!(mark_seen(&mut db, &windows, 1000, |_| None));
+        assert_eq!(character(&db, "Iop").last_seen, Some(1000));
+        assert_eq!(character(&db, "Cra").last_seen, None);
+
+        assert!(!mark_seen(
+            &mut db,
+            &windows,
+            1000 + SEEN_INTERVAL - 1,
+            |_| None
+        ));
+
+        desktop.open_window("Eni");
+        let windows = desktop.get_windows().unwrap();
+        assert!(mark_seen(&mut db, &windows, 1000 + SEEN_INTERVAL, |_| None));
+        assert_eq!(character(&db, "Iop").last_seen, Some(1000 + SEEN_INTERVAL));
+        assert_eq!(character(&db, "Eni").last_seen, Some(1000 + SEEN_INTERVAL));
+    }
+
+    #[test]
+    fn fill_characters_from_titles() {
+        let desktop = FakeDesktop::new();
+        let iop = desktop.open_window("Iop");
+        desktop.open_window("Cra");
+        let windows = desktop.get_windows().unwrap();
+
+        let mut db = database(&[("solo", &["Cra"])]);
+        db.character_entry(&"Cra".into()).breed = Some(Breed::Sram);
+
+        let read_title = |id| {
+            let name = if id == iop { "Iop" } else { "Cra" };
+
+            Some(WindowMatch {
+                breed: Some(Breed::parse(name).unwrap()),
+                level: Some(50),
+                ..WindowMatch::new(name)
+            })
+        };
+        mark_seen(&mut db, &windows, 1000, read_title);
+
+        assert_eq!(character(&db, "Iop").breed, Some(Breed::Iop));
+        assert_eq!(character(&db, "Iop").level, Some(50));
+        // The breed chosen by the user wins
+        assert_eq!(character(&db, "Cra").breed, Some(Breed::Sram));
+        assert_eq!(character(&db, "Cra").level, Some(50));
+    }
+
+    #[test]
+    fn same_name_on_several_servers() {
+        let desktop = FakeDesktop::new();
+        let imagiro = desktop.open_window(CharacterKey::new("Iop", Some("Imagiro".to_owned())));
+        let tylezia = desktop.open_window(CharacterKey::new("Iop", Some("Tylezia".to_owned())));
+        let windows = desktop.get_windows().unwrap();
+
+        let mut db = Database::default();
+        mark_seen(&mut db, &windows, 1000, |_| None);
+        assert_eq!(db.characters.len(), 2);
+
+        let mut group = Group::new("duo");
+        for character in db.characters.iter() {
+            group
+                .members
+                .push(Member::new(character.id, group.free_slot()));
+        }
+        db.groups.push(group);
+
+        let wins = group_windows(&db, &db.groups[0], &windows);
+        assert_eq!(
+            wins.iter().map(|(win, _)| *win).collect::<Vec<_>>(),
+            vec![imagiro, tylezia]
+        );
+
+        // Without the server both windows show the same character
+        let other = desktop.open_window("Cra");
+        desktop.open_window("Cra");
+        let windows = desktop.get_windows().unwrap();
+        let db = database(&[("solo", &["Cra"])]);
+
+        let wins = group_windows(&db, &db.groups[0], &windows);
+        assert_eq!(wins.len(), 2);
+        assert_eq!(wins[0].0, other);
+    }
+
+    #[test]
+    fn slots_reach_every_window_of_a_name() {
+        let desktop = FakeDesktop::new();
+        let first = desktop.open_window("Iop");
+        let second = desktop.open_window("Iop");
+        let windows = desktop.get_windows().unwrap();
+
+        // Titles without server match both characters
+        let mut db = Database::default();
+        let mut group = Group::new("duo");
+        for server in ["Imagiro", "Tylezia"] {
+            let id = db
+                .character_entry(&CharacterKey::new("Iop", Some(server.to_owned())))
+                .id;
+            group.members.push(Member::new(id, group.free_slot()));
+        }
+        db.groups.push(group);
+
+        let slots = group_slots(&db, &db.groups[0], &windows);
+        assert_eq!(
+            slots.iter().map(|s| s.window).collect::<Vec<_>>(),
+            vec![Some(first), Some(second)]
+        );
+        assert_eq!(group_windows(&db, &db.groups[0], &windows).len(), 2);
+    }
+
+    #[test]
+    fn characters_saved_without_server() {
+        let desktop = FakeDesktop::new();
+        let imagiro = desktop.open_window(CharacterKey::new("Iop", Some("Imagiro".to_owned())));
+        let windows = desktop.get_windows().unwrap();
+
+        let mut db = database(&[("solo", &["Iop"])]);
+        assert_eq!(
+            group_slots(&db, &db.groups[0], &windows)[0].window,
+            Some(imagiro)
+        );
+
+        // The first window seen gives it its server
+        mark_seen(&mut db, &windows, 1000, |_| None);
+        assert_eq!(db.characters.len(), 1);
+        assert_eq!(db.characters[0].server.as_deref(), Some("Imagiro"));
+    }
+
+    #[test]
+    fn pick_group_without_groups() {
+        assert!(pick_group(&Database::default(), &Windows::new()).is_none());
+    }
 }
@@ -1,8 +1,15 @@
 use anyhow::{anyhow, Result};
+use rustix::event::{poll, PollFd, PollFlags};
+use rustix::io::Errno;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::thread::sleep;
-use std::time::Duration;
-use x11rb::protocol::xproto::{ConfigureWindowAux, InputFocus, StackMode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use x11rb::protocol::xproto::{
+    ChangeWindowAttributesAux, ClientMessageEvent, ConfigureWindowAux, CreateWindowAux, EventMask,
+    InputFocus, PropMode, StackMode, Timestamp, WindowClass,
+};
+use x11rb::protocol::Event;
 use x11rb::wrapper::ConnectionExt as WConnectionExt;
 use x11rb::{
     atom_manager,
@@ -11,15 +18,22 @@ use x11rb::{
     rust_connection::RustConnection,
 };
 
+use super::{
+    matcher::{get_generation, get_rules, WindowMatch, WmClass},
+    Desktop, FocusError, FocusStrategy, WindowEvent, Windows,
+};
+use crate::database::CharacterKey;
+
 atom_manager! {
     pub AtomCollection: AtomCollectionCookie {
         _NET_CLIENT_LIST,
+        _NET_SUPPORTED,
         _NET_WM_NAME,
         _NET_WM_STATE,
         _NET_WM_STATE_HIDDEN,
-        _NET_WM_STATE_FOCUSED,
         _NET_ACTIVE_WINDOW,
         WM_CLASS,
+        WM_NAME,
         UTF8_STRING,
         STRING,
     }
@@ -29,10 +43,21 @@ pub struct X11Desktop {
     conn: RustConnection,
     screen: usize,
     atoms: AtomCollection,
+    /// Client windows seen by `wait_for_changes` with their character, if
+    /// any, `None` until the root window is subscribed to
+    tracked: RefCell<Option<HashMap<Window, Option<CharacterKey>>>>,
+    /// Rules the tracked windows were checked with, see `get_generation`
+    generation: Cell<u64>,
 }
 
-impl X11Desktop {
-    pub fn connect() -> Result<Self> {
+/// How often `wait_for_changes` looks for new rules while nothing happens
+const RULES_CHECK: Duration = Duration::from_secs(1);
+
+/// How often `focus` looks for a cancellation while it waits
+const CANCEL_CHECK: Duration = Duration::from_millis(50);
+
+impl Desktop for X11Desktop {
+    fn connect() -> Result<Self> {
         let (conn, screen) = x11rb::connect(None)?;
         let atoms = AtomCollection::new(&conn)?.reply()?;
 
@@ -40,40 +65,309 @@ impl X11Desktop {
             conn,
             screen,
             atoms,
+            tracked: Default::default(),
+            generation: Default::default(),
         })
     }
 
-    pub fn show_window(&self, id: u32) -> Result<()> {
-        self.conn
-            .set_input_focus(InputFocus::POINTER_ROOT, id.to_owned(), x11rb::CURRENT_TIME)?;
+    fn show_window(
+        &self,
+        id: u32,
+        strategy: FocusStrategy,
+        timeout: Duration,
+        cancelled: &AtomicBool,
+    ) -> Result<(), FocusError> {
+        Ok(self.focus(id, strategy, timeout, cancelled)?)
+    }
+
+    fn get_active_window(&self) -> Result<Option<u32>> {
+        let active_window = self.get_active_window_id()?;
+
+        if !get_rules().is_dofus(&self.get_class(active_window)?) {
+            return Ok(None);
+        }
+
+        Ok(Some(active_window))
+    }
+
+    fn get_windows(&self) -> Result<Windows> {
+        let mut dofus_windows = Windows::new();
+
+        for window in self.get_client_list()? {
+            if let Some(character) = self.get_character(window)? {
+                dofus_windows.insert(window, character);
+            }
+        }
+
+        Ok(dofus_windows)
+    }
+
+    fn read_title(&self, id: u32) -> Result<Option<WindowMatch>> {
+        let class = self.get_class(id)?;
+
+        let Some(title) = self.get_title(id)? else {
+            return Ok(None);
+        };
+
+        Ok(get_rules().find(&class, &title))
+    }
+
+    fn wait_for_changes(&self) -> Result<Vec<WindowEvent>> {
+        let root = self.conn.setup().roots[self.screen].root;
+
+        if self.tracked.borrow().is_none() {
+            self.subscribe(root)?;
+            *self.tracked.borrow_mut() = Some(Default::default());
+            self.generation.set(get_generation());
+
+            let events = self.sync_client_list()?;
+
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+
+        loop {
+            let generation = get_generation();
+
+            let events = if generation != self.generation.get() {
+                self.generation.set(generation);
+                self.sync_tracked()
+            } else {
+                let Some(Event::PropertyNotify(event)) =
+                    self.wait_for_event_until(Instant::now() + RULES_CHECK)?
+                else {
+                    continue;
+                };
+
+                if event.window == root && event.atom == self.atoms._NET_CLIENT_LIST {
+                    self.sync_client_list()?
+                } else if [
+                    self.atoms._NET_WM_NAME,
+                    self.atoms.WM_NAME,
+                    self.atoms.WM_CLASS,
+                    self.atoms._NET_WM_STATE,
+                ]
+                .contains(&event.atom)
+                {
+                    self.sync_window(event.window)
+                } else {
+                    continue;
+                }
+            };
+
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+impl X11Desktop {
+    fn focus(
+        &self,
+        id: u32,
+        strategy: FocusStrategy,
+        timeout: Duration,
+        cancelled: &AtomicBool,
+    ) -> Result<()> {
+        let root = self.conn.setup().roots[self.screen].root;
+
+        if self.conn.get_window_attributes(id)?.reply().is_err() {
+            return Err(FocusError::WindowGone.into());
+        }
+
+        let strategy = match strategy {
+            FocusStrategy::Auto if self.supports_active_window()? => FocusStrategy::Ewmh,
+            FocusStrategy::Auto => FocusStrategy::InputFocus,
+            strategy => strategy,
+        };
+
+        let timestamp = self.get_timestamp()?;
 
-        self.conn.configure_window(
-            id.to_owned(),
-            &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE),
+        self.conn.change_window_attributes(
+            id,
+            &ChangeWindowAttributesAux::new()
+                .event_mask(EventMask::FOCUS_CHANGE | EventMask::STRUCTURE_NOTIFY),
         )?;
+        self.subscribe(root)?;
+
+        // Events left over from a previous call would be mistaken for the
+        // window manager's answer
+        while self.conn.poll_for_event()?.is_some() {}
+
+        if strategy == FocusStrategy::Ewmh {
+            let active_window = self.get_active_window_id()?;
+
+            // Source indication 2 tells the window manager the request comes
+            // from a pager, which bypasses focus stealing prevention
+            let event = ClientMessageEvent::new(
+                32,
+                id,
+                self.atoms._NET_ACTIVE_WINDOW,
+                [2, timestamp, active_window, 0, 0],
+            );
+
+            self.conn.send_event(
+                false,
+                root,
+                EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                event,
+            )?;
+        } else {
+            if self.is_hidden(id)? {
+                self.conn.map_window(id)?;
+            }
+
+            self.conn
+                .set_input_focus(InputFocus::POINTER_ROOT, id, timestamp)?;
+
+            self.conn
+                .configure_window(id, &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE))?;
+        }
 
         self.conn.sync()?;
 
-        let mut i = 0;
+        // Window managers without _NET_ACTIVE_WINDOW only answer with FocusIn
+        if self.get_active_window_id().ok() == Some(id) {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + timeout;
+        // Window managers may activate another window on the way, e.g. a
+        // transient one, only the last one counts
+        let mut refused = false;
 
         loop {
-            sleep(Duration::from_millis(100));
+            if cancelled.load(Ordering::SeqCst) {
+                return Err(FocusError::Cancelled.into());
+            }
+
+            // Cancellations come from another thread, they are checked
+            // between slices of the wait
+            let Some(event) =
+                self.wait_for_event_until(deadline.min(Instant::now() + CANCEL_CHECK))?
+            else {
+                if Instant::now() >= deadline {
+                    break;
+                }
+
+                continue;
+            };
 
-            if self.is_focus(id.to_owned())? {
-                break;
+            match event {
+                Event::FocusIn(event) if event.event == id => return Ok(()),
+                Event::DestroyNotify(event) if event.window == id => {
+                    return Err(FocusError::WindowGone.into())
+                }
+                Event::PropertyNotify(event)
+                    if event.window == root && event.atom == self.atoms._NET_ACTIVE_WINDOW =>
+                {
+                    match self.get_active_window_id()? {
+                        active if active == id => return Ok(()),
+                        x11rb::NONE => refused = false,
+                        _ => refused = true,
+                    }
+                }
+                _ => {}
             }
+        }
+
+        if refused {
+            Err(FocusError::Refused.into())
+        } else {
+            Err(FocusError::Timeout.into())
+        }
+    }
 
-            i += 1;
-            if i > 300 {
-                break;
+    /// The next event, `None` once `deadline` is reached. Sleeps on the
+    /// connection until the server sends something
+    fn wait_for_event_until(&self, deadline: Instant) -> Result<Option<Event>> {
+        loop {
+            if let Some(event) = self.conn.poll_for_event()? {
+                return Ok(Some(event));
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+
+            let timeout = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+            let mut fds = [PollFd::new(self.conn.stream(), PollFlags::IN)];
+
+            match poll(&mut fds, timeout) {
+                Ok(_) | Err(Errno::INTR) => {}
+                Err(e) => return Err(e.into()),
             }
         }
+    }
 
-        Ok(())
+    fn supports_active_window(&self) -> Result<bool> {
+        let screen = &self.conn.setup().roots[self.screen];
+        let supported = self
+            .conn
+            .get_property(
+                false,
+                screen.root,
+                self.atoms._NET_SUPPORTED,
+                AtomEnum::ATOM,
+                0,
+                u32::MAX,
+            )?
+            .reply()?
+            .value32()
+            .is_some_and(|mut atoms| atoms.any(|a| a == self.atoms._NET_ACTIVE_WINDOW));
+
+        Ok(supported)
+    }
+
+    /// Gets the current server time by touching a property on a throwaway
+    /// window, window managers ignore requests stamped with `CURRENT_TIME`
+    fn get_timestamp(&self) -> Result<Timestamp> {
+        let root = self.conn.setup().roots[self.screen].root;
+        let window = self.conn.generate_id()?;
+
+        self.conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            -1,
+            -1,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+
+        self.conn.change_property8(
+            PropMode::APPEND,
+            window,
+            self.atoms._NET_WM_NAME,
+            self.atoms.UTF8_STRING,
+            &[],
+        )?;
+        self.conn.flush()?;
+
+        let timestamp = loop {
+            if let Event::PropertyNotify(event) = self.conn.wait_for_event()? {
+                if event.window == window {
+                    break event.time;
+                }
+            }
+        };
+
+        self.conn.destroy_window(window)?;
+        self.conn.flush()?;
+
+        Ok(timestamp)
     }
 
-    pub fn get_active_window(&self) -> Result<Option<u32>> {
-        let screen = &self.conn.setup().roots[self.screen.to_owned()];
+    fn get_active_window_id(&self) -> Result<Window> {
+        let screen = &self.conn.setup().roots[self.screen];
         let active_window = self
             .conn
             .get_property(
@@ -90,14 +384,10 @@ impl X11Desktop {
             .next()
             .ok_or(anyhow!("_NET_ACTIVE_WINDOW is empty"))?;
 
-        if !self.get_process_name(active_window)?.contains("dofus.exe") {
-            return Ok(None);
-        }
-
-        Ok(Some(active_window))
+        Ok(active_window)
     }
 
-    pub fn get_windows(&self) -> Result<HashMap<String, u32>> {
+    fn get_client_list(&self) -> Result<Vec<Window>> {
         let screen = &self.conn.setup().roots[self.screen];
         let windows = self
             .conn
@@ -114,32 +404,108 @@ impl X11Desktop {
             .ok_or(anyhow!("value32 is none when trying to get client list"))?
             .collect::<Vec<Window>>();
 
-        let mut dofus_windows = HashMap::new();
+        Ok(windows)
+    }
 
-        for window in windows {
-            if !self.get_process_name(window)?.contains("dofus.exe") {
-                continue;
+    /// The character shown by a Dofus window, minimized windows are kept since
+    /// `show_window` restores them
+    fn get_character(&self, window: Window) -> Result<Option<CharacterKey>> {
+        Ok(self.read_title(window)?.map(|m| m.key()))
+    }
+
+    fn subscribe(&self, window: Window) -> Result<()> {
+        self.conn.change_window_attributes(
+            window,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+        self.conn.flush()?;
+
+        Ok(())
+    }
+
+    /// Diffs `_NET_CLIENT_LIST` against the tracked windows and subscribes to
+    /// the new ones, Dofus or not since they may become one when renamed or
+    /// when the rules change
+    fn sync_client_list(&self) -> Result<Vec<WindowEvent>> {
+        let windows = self.get_client_list()?;
+        let mut tracked = self.tracked.borrow_mut();
+        let tracked = tracked.get_or_insert_with(Default::default);
+        let mut events = vec![];
+
+        tracked.retain(|id, name| {
+            if windows.contains(id) {
+                return true;
             }
 
-            if self.is_hidden(window)? {
-                continue;
+            if name.is_some() {
+                events.push(WindowEvent::Removed { id: id.to_owned() });
             }
 
-            let Some(name) = self.get_name(window)? else {
-                continue;
-            };
+            false
+        });
 
-            if name == "Dofus" {
+        for window in windows {
+            if tracked.contains_key(&window) {
                 continue;
             }
 
-            dofus_windows.insert(name, window);
+            // Windows destroyed in the meantime are dropped at the next sync
+            let _ = self.subscribe(window);
+            let character = self.get_character(window).unwrap_or_default();
+
+            if let Some(character) = character.to_owned() {
+                events.push(WindowEvent::Updated {
+                    id: window,
+                    character,
+                });
+            }
+
+            tracked.insert(window, character);
         }
 
-        Ok(dofus_windows)
+        Ok(events)
     }
 
-    fn get_name(&self, id: u32) -> Result<Option<String>> {
+    /// Runs the rules again on every tracked window
+    fn sync_tracked(&self) -> Vec<WindowEvent> {
+        let windows = self
+            .tracked
+            .borrow()
+            .iter()
+            .flat_map(|tracked| tracked.keys().copied())
+            .collect::<Vec<_>>();
+
+        windows
+            .into_iter()
+            .flat_map(|window| self.sync_window(window))
+            .collect()
+    }
+
+    fn sync_window(&self, window: Window) -> Vec<WindowEvent> {
+        let mut tracked = self.tracked.borrow_mut();
+
+        let Some(current) = tracked.as_mut().and_then(|t| t.get_mut(&window)) else {
+            return vec![];
+        };
+
+        let character = self.get_character(window).unwrap_or_default();
+
+        if *current == character {
+            return vec![];
+        }
+
+        current.clone_from(&character);
+
+        match character {
+            Some(character) => vec![WindowEvent::Updated {
+                id: window,
+                character,
+            }],
+            None => vec![WindowEvent::Removed { id: window }],
+        }
+    }
+
+    fn get_title(&self, id: u32) -> Result<Option<String>> {
         let prop = self
             .conn
             .get_property(
@@ -152,13 +518,14 @@ impl X11Desktop {
             )?
             .reply()?;
 
-        let wm_name = String::from_utf8(prop.value)?;
-        let name = wm_name.split(" ").next();
+        if prop.value.is_empty() {
+            return Ok(None);
+        }
 
-        Ok(name.map(|n| n.to_owned()))
+        Ok(Some(String::from_utf8(prop.value)?))
     }
 
-    fn get_process_name(&self, id: u32) -> Result<String> {
+    fn get_class(&self, id: u32) -> Result<WmClass> {
         let prop = self
             .conn
             .get_property(
@@ -171,10 +538,7 @@ impl X11Desktop {
             )?
             .reply()?;
 
-        let wm_class = String::from_utf8(prop.value)?;
-        let (name, _) = wm_class.split_at(wm_class.char_indices().count() / 2);
-
-        Ok(name.to_owned())
+        Ok(WmClass::parse(&prop.value))
     }
 
     fn is_hidden(&self, id: u32) -> Result<bool> {
@@ -197,25 +561,4 @@ impl X11Desktop {
 
         Ok(hidden)
     }
-
-    fn is_focus(&self, id: u32) -> Result<bool> {
-        let prop = self
-            .conn
-            .get_property(
-                false,
-                id,
-                self.atoms._NET_WM_STATE,
-                AtomEnum::ATOM,
-                0,
-                u32::MAX,
-            )?
-            .reply()?;
-
-        let focus = prop
-            .value32()
-            .ok_or(anyhow!("value32 is none when trying to get wn state"))?
-            .any(|s| s == self.atoms._NET_WM_STATE_FOCUSED);
-
-        Ok(focus)
-    }
 }
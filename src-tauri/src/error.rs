@@ -0,0 +1,99 @@
+use serde::Serialize;
+use std::{fmt, sync::PoisonError};
+
+/// Error returned by the commands, serialized as `{ kind, message }` for the
+/// frontend
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", content = "message")]
+pub enum CommandError {
+    /// No group or character with this id
+    NotFound(String),
+    /// A group or character with this name already exists
+    Duplicate(String),
+    /// A name or value given by the user is not allowed
+    Invalid(String),
+    /// The database or the desktop could not be read or written
+    Io(String),
+    /// A thread panicked while holding the database
+    Lock,
+    /// The database could not be parsed or serialized
+    Parse(String),
+    /// The database file was changed by another program in a way that
+    /// cannot be merged, lists what both sides changed
+    Conflict(String),
+}
+
+impl CommandError {
+    pub fn not_found(what: impl fmt::Display) -> Self {
+        CommandError::NotFound(what.to_string())
+    }
+
+    pub fn duplicate(what: impl fmt::Display) -> Self {
+        CommandError::Duplicate(what.to_string())
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotFound(what) => write!(f, "{what} not found"),
+            CommandError::Duplicate(what) => write!(f, "{what} already exists"),
+            CommandError::Invalid(e) => write!(f, "{e}"),
+            CommandError::Io(e) => write!(f, "io error: {e}"),
+            CommandError::Lock => write!(f, "the database is poisoned"),
+            CommandError::Parse(e) => write!(f, "invalid database: {e}"),
+            CommandError::Conflict(fields) => {
+                write!(f, "the database file was edited elsewhere: {fields}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl<T> From<PoisonError<T>> for CommandError {
+    fn from(_: PoisonError<T>) -> Self {
+        CommandError::Lock
+    }
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(e: std::io::Error) -> Self {
+        CommandError::Io(e.to_string())
+    }
+}
+
+impl From<toml::de::Error> for CommandError {
+    fn from(e: toml::de::Error) -> Self {
+        CommandError::Parse(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for CommandError {
+    fn from(e: serde_json::Error) -> Self {
+        CommandError::Parse(e.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for CommandError {
+    fn from(e: toml::ser::Error) -> Self {
+        CommandError::Parse(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_for_the_frontend() {
+        assert_eq!(
+            serde_json::to_value(CommandError::not_found("group 42")).unwrap(),
+            serde_json::json!({ "kind": "NotFound", "message": "group 42" })
+        );
+        assert_eq!(
+            serde_json::to_value(CommandError::Lock).unwrap(),
+            serde_json::json!({ "kind": "Lock" })
+        );
+    }
+}
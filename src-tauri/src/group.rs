@@ -1,187 +1,651 @@
+use serde::Serialize;
+use uuid::Uuid;
+
 use crate::{
-    database::{get_database, Breed, Character, Group},
-    desktop::Desktop,
+    database::{get_database, Breed, Character, CharacterKey, Database, Group, Member},
+    desktop::{self, get_windows},
+    error::CommandError,
 };
 
+/// A member with its character, as shown by the frontend
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct MemberView {
+    #[serde(flatten)]
+    pub character: Character,
+    pub enabled: bool,
+    pub slot: usize,
+}
+
+/// A group with its members resolved
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct GroupView {
+    pub id: Uuid,
+    pub name: String,
+    pub characters: Vec<MemberView>,
+    /// See `Group::leader`
+    pub leader: Option<Uuid>,
+}
+
+pub fn views(db: &Database) -> Vec<GroupView> {
+    db.groups
+        .iter()
+        .map(|group| GroupView {
+            id: group.id,
+            name: group.name.to_owned(),
+            characters: db
+                .members(group)
+                .map(|(member, character)| MemberView {
+                    character: character.clone(),
+                    enabled: member.enabled,
+                    slot: member.slot,
+                })
+                .collect(),
+            leader: group.leader(),
+        })
+        .collect()
+}
+
 #[tauri::command]
-pub fn get_available_characters() -> Vec<Character> {
-    let Ok(db) = get_database().read() else {
-        return vec![];
-    };
+pub fn get_available_characters() -> Result<Vec<Character>, CommandError> {
+    let windows = get_windows().read()?.clone();
+    let db = get_database().read()?;
 
-    let Ok(desktop) = Desktop::connect() else {
-        return vec![];
-    };
+    // Windows showing the same character are listed once
+    let mut keys = windows.into_values().collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+
+    Ok(keys
+        .iter()
+        .map(|key| db.character(key).cloned().unwrap_or_else(|| key.into()))
+        .filter(|c| !c.archived)
+        .collect::<Vec<_>>())
+}
+
+#[tauri::command]
+pub fn get_active_group() -> Result<Option<Uuid>, CommandError> {
+    let db = get_database().read()?;
+
+    Ok(db.active_group().map(|g| g.id))
+}
+
+/// Selects the group the hotkeys act on and leaves auto mode
+#[tauri::command]
+pub fn set_active_group(id: Uuid) -> Result<Uuid, CommandError> {
+    {
+        let mut db = get_database().write()?;
+
+        db.update(|db| {
+            find_group_mut(db, id)?;
+
+            db.active_group = Some(id);
+            db.settings.auto_group = false;
+
+            Ok(())
+        })?;
+    }
+
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn set_auto_group(value: bool) -> Result<bool, CommandError> {
+    {
+        let mut db = get_database().write()?;
+
+        db.update(|db| {
+            db.settings.auto_group = value;
+            Ok(())
+        })?;
+    }
+
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    Ok(value)
+}
+
+/// Selects the next group after the active one, or the previous one when
+/// `reverse` is set
+pub fn cycle_active_group(reverse: bool) -> Result<(), CommandError> {
+    let id = {
+        let windows = get_windows().read()?;
+        let db = get_database().read()?;
+
+        if db.groups.is_empty() {
+            return Ok(());
+        }
+
+        let current = desktop::get_active_group(&db, &windows);
+
+        let pos = current
+            .and_then(|current| db.groups.iter().position(|g| g.id == current.id))
+            .unwrap_or_default();
+
+        let next_pos = if reverse {
+            (pos + db.groups.len() - 1) % db.groups.len()
+        } else {
+            (pos + 1) % db.groups.len()
+        };
 
-    let Ok(wins) = desktop.get_windows() else {
-        return vec![];
+        db.groups[next_pos].id
     };
 
-    wins.iter()
-        .map(|(name, _)| {
-            db.characters
-                .get(name)
-                .cloned()
-                .unwrap_or_else(|| Character::new(name))
-        })
-        .collect::<Vec<_>>()
+    set_active_group(id)?;
+
+    Ok(())
+}
+
+fn find_group_mut(db: &mut Database, id: Uuid) -> Result<&mut Group, CommandError> {
+    db.find_group_mut(id)
+        .ok_or_else(|| CommandError::not_found(format!("group {id}")))
+}
+
+#[tauri::command]
+pub fn get_groups() -> Result<Vec<GroupView>, CommandError> {
+    let db = get_database().read()?;
+
+    Ok(views(&db))
+}
+
+/// Trims the name and rejects names made of whitespace only
+pub fn trim_name(name: &str) -> Result<&str, CommandError> {
+    let name = name.trim();
+
+    if name.is_empty() {
+        return Err(CommandError::Invalid(
+            "group names cannot be empty".to_owned(),
+        ));
+    }
+
+    Ok(name)
+}
+
+/// Trims the name and checks that no other group than `id` uses it
+fn validate_name(db: &Database, name: &str, id: Option<Uuid>) -> Result<String, CommandError> {
+    let name = trim_name(name)?;
+
+    if db.groups.iter().any(|g| g.name == name && Some(g.id) != id) {
+        return Err(CommandError::duplicate(format!("group {name}")));
+    }
+
+    Ok(name.to_owned())
 }
 
 #[tauri::command]
-pub fn get_groups() -> Vec<Group> {
-    let Ok(db) = get_database().read() else {
-        return vec![];
+pub fn create_group(name: String) -> Result<Vec<GroupView>, CommandError> {
+    let groups = {
+        let mut db = get_database().write()?;
+
+        db.update(|db| {
+            let name = validate_name(db, &name, None)?;
+            db.groups.insert(0, Group::new(name));
+
+            Ok(())
+        })?;
+
+        views(&db)
     };
 
-    db.groups.clone()
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    Ok(groups)
 }
 
 #[tauri::command]
-pub fn create_group(name: String) -> Vec<Group> {
-    let Ok(mut db) = get_database().write() else {
-        return vec![];
+pub fn rename_group(id: Uuid, name: String) -> Result<Vec<GroupView>, CommandError> {
+    let groups = {
+        let mut db = get_database().write()?;
+
+        db.update(|db| {
+            let name = validate_name(db, &name, Some(id))?;
+            find_group_mut(db, id)?.name = name;
+
+            Ok(())
+        })?;
+
+        views(&db)
     };
 
-    db.groups.splice(
-        0..0,
-        vec![Group {
-            name,
-            characters: Default::default(),
-        }],
-    );
+    #[cfg(desktop)]
+    crate::tray::refresh();
 
-    db.save();
-    db.groups.clone()
+    Ok(groups)
 }
 
+/// Copies the group right after it, under `name` or a free variant of its
+/// name
 #[tauri::command]
-pub fn delete_group(id: usize) -> Vec<Group> {
-    let Ok(mut db) = get_database().write() else {
-        return vec![];
+pub fn duplicate_group(id: Uuid, name: Option<String>) -> Result<Vec<GroupView>, CommandError> {
+    let groups = {
+        let mut db = get_database().write()?;
+
+        db.update(|db| {
+            let pos = group_position(db, id)?;
+            let name = match name {
+                Some(name) => validate_name(db, &name, None)?,
+                None => db.free_group_name(&db.groups[pos].name),
+            };
+
+            let mut copy = Group::new(name);
+            copy.members = db.groups[pos].members.clone();
+            db.groups.insert(pos + 1, copy);
+
+            Ok(())
+        })?;
+
+        views(&db)
     };
 
-    db.groups.remove(id);
+    #[cfg(desktop)]
+    crate::tray::refresh();
 
-    db.save();
-    db.groups.clone()
+    Ok(groups)
 }
 
+/// Moves the group to `index`, or to the end when it is past it
 #[tauri::command]
-pub fn add_character_to_group(id: usize, name: String) -> Vec<Group> {
-    let Ok(mut db) = get_database().write() else {
-        return vec![];
+pub fn move_group(id: Uuid, index: usize) -> Result<Vec<GroupView>, CommandError> {
+    let groups = {
+        let mut db = get_database().write()?;
+
+        db.update(|db| {
+            let pos = group_position(db, id)?;
+            let group = db.groups.remove(pos);
+            let index = index.min(db.groups.len());
+            db.groups.insert(index, group);
+
+            Ok(())
+        })?;
+
+        views(&db)
     };
 
-    if db.groups.get(id).is_none() {
-        return db.groups.clone();
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    Ok(groups)
+}
+
+fn group_position(db: &Database, id: Uuid) -> Result<usize, CommandError> {
+    db.groups
+        .iter()
+        .position(|g| g.id == id)
+        .ok_or_else(|| CommandError::not_found(format!("group {id}")))
+}
+
+#[tauri::command]
+pub fn delete_group(id: Uuid) -> Result<Vec<GroupView>, CommandError> {
+    let groups = {
+        let mut db = get_database().write()?;
+
+        db.update(|db| {
+            find_group_mut(db, id)?;
+            db.groups.retain(|g| g.id != id);
+
+            if db.active_group == Some(id) {
+                db.active_group = None;
+            }
+
+            Ok(())
+        })?;
+
+        views(&db)
     };
 
-    if db.groups[id].characters.iter().any(|c| c.name == name) {
-        return db.groups.clone();
-    }
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    Ok(groups)
+}
+
+#[tauri::command]
+pub fn add_character_to_group(
+    id: Uuid,
+    name: String,
+    server: Option<String>,
+) -> Result<Vec<GroupView>, CommandError> {
+    let mut db = get_database().write()?;
+
+    db.update(|db| {
+        let key = CharacterKey::new(name, server);
+        let character_id = db.character_entry(&key).id;
+        let group = find_group_mut(db, id)?;
+
+        if group.members.iter().any(|m| m.id == character_id) {
+            return Err(CommandError::duplicate(format!(
+                "{key} in group {}",
+                group.name
+            )));
+        }
 
-    let character = db
-        .characters
-        .get(&name)
-        .cloned()
-        .unwrap_or_else(|| Character::new(name));
+        let slot = group.free_slot();
+        group.members.push(Member::new(character_id, slot));
 
-    db.groups[id].characters.push(character);
+        Ok(())
+    })?;
 
-    db.save();
-    db.groups.clone()
+    Ok(views(&db))
 }
 
 #[tauri::command]
 pub fn add_character_to_group_at(
-    id: usize,
+    id: Uuid,
     name: String,
-    target_name: String,
+    server: Option<String>,
+    target_id: Uuid,
     right: bool,
-) -> Vec<Group> {
-    let Ok(mut db) = get_database().write() else {
-        return vec![];
-    };
+) -> Result<Vec<GroupView>, CommandError> {
+    let mut db = get_database().write()?;
+
+    db.update(|db| {
+        let character_id = db.character_entry(&CharacterKey::new(name, server)).id;
+        let group = find_group_mut(db, id)?;
+
+        // Moving inside the group keeps the member settings
+        let member = match group.members.iter().position(|m| m.id == character_id) {
+            Some(index) => group.members.remove(index),
+            None => Member::new(character_id, group.free_slot()),
+        };
+
+        match group.members.iter().position(|m| m.id == target_id) {
+            Some(index) if right => group.members.insert(index + 1, member),
+            Some(index) => group.members.insert(index, member),
+            None => group.members.push(member),
+        }
 
-    if db.groups.get(id).is_none() {
-        return db.groups.clone();
-    };
+        Ok(())
+    })?;
 
-    db.groups[id].characters.retain(|c| c.name != name);
+    Ok(views(&db))
+}
 
-    let character = db
-        .characters
-        .get(&name)
-        .cloned()
-        .unwrap_or_else(|| Character::new(name));
+#[tauri::command]
+pub fn remove_character_from_group(
+    id: Uuid,
+    character_id: Uuid,
+) -> Result<Vec<GroupView>, CommandError> {
+    let mut db = get_database().write()?;
 
-    db.groups[id]
-        .characters
-        .iter()
-        .position(|c| c.name == target_name)
-        .map(|index| {
-            if right {
-                db.groups[id].characters.insert(index + 1, character);
-            } else {
-                db.groups[id]
-                    .characters
-                    .splice(index..index, vec![character]);
-            }
-        });
+    db.update(|db| {
+        let group = find_group_mut(db, id)?;
 
-    db.save();
-    db.groups.clone()
+        if !group.members.iter().any(|m| m.id == character_id) {
+            return Err(CommandError::not_found(format!("character {character_id}")));
+        }
+
+        group.members.retain(|m| m.id != character_id);
+
+        if group.leader == Some(character_id) {
+            group.leader = None;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(views(&db))
 }
 
+/// Includes or skips the character when cycling through this group only
 #[tauri::command]
-pub fn remove_character_from_group(id: usize, character_id: usize) -> Vec<Group> {
-    let Ok(mut db) = get_database().write() else {
-        return vec![];
-    };
-    if db.groups.get(id).is_none() {
-        return db.groups.clone();
-    };
+pub fn set_character_enabled(
+    id: Uuid,
+    character_id: Uuid,
+    value: bool,
+) -> Result<Vec<GroupView>, CommandError> {
+    let mut db = get_database().write()?;
+
+    db.update(|db| {
+        let member = find_group_mut(db, id)?
+            .members
+            .iter_mut()
+            .find(|m| m.id == character_id)
+            .ok_or_else(|| CommandError::not_found(format!("character {character_id}")))?;
+
+        member.enabled = value;
+
+        Ok(())
+    })?;
+
+    Ok(views(&db))
+}
+
+fn set_leader(group: &mut Group, character_id: Uuid) -> Result<(), CommandError> {
+    if !group.members.iter().any(|m| m.id == character_id) {
+        return Err(CommandError::not_found(format!("character {character_id}")));
+    }
 
-    db.groups[id].characters.remove(character_id);
-    db.save();
-    db.groups.clone()
+    group.leader = Some(character_id);
+
+    Ok(())
 }
 
+/// Designates the member leading travels, invites and clicks, the first
+/// member leads when `character_id` is `None`
 #[tauri::command]
-pub fn set_character_enabled(id: usize, character_id: usize, value: bool) -> Vec<Group> {
-    let Ok(mut db) = get_database().write() else {
-        return vec![];
-    };
+pub fn set_group_leader(
+    id: Uuid,
+    character_id: Option<Uuid>,
+) -> Result<Vec<GroupView>, CommandError> {
+    let mut db = get_database().write()?;
+
+    db.update(|db| {
+        let group = find_group_mut(db, id)?;
+
+        match character_id {
+            Some(character_id) => set_leader(group, character_id)?,
+            None => group.leader = None,
+        }
 
-    if db.groups.get(id).is_none() {
-        return db.groups.clone();
+        Ok(())
+    })?;
+
+    Ok(views(&db))
+}
+
+/// Makes the character shown by `window` the leader of the active group,
+/// nothing happens when it is not one of its members
+pub fn promote_leader(window: u32) -> Result<(), CommandError> {
+    // Copied so that the window watcher is not blocked during the save
+    let windows = get_windows().read()?.clone();
+    let mut db = get_database().write()?;
+
+    let Some(character_id) = windows
+        .get(&window)
+        .and_then(|key| db.character(key))
+        .map(|c| c.id)
+    else {
+        return Ok(());
     };
 
-    if db.groups[id].characters.get(character_id).is_none() {
-        return db.groups.clone();
+    let Some(id) = desktop::get_active_group(&db, &windows).map(|g| g.id) else {
+        return Ok(());
     };
 
-    db.groups[id].characters[character_id].enabled = value;
-    db.save();
-    db.groups.clone()
+    let is_follower = db.find_group(id).is_some_and(|g| {
+        g.leader() != Some(character_id) && g.members.iter().any(|m| m.id == character_id)
+    });
+
+    if !is_follower {
+        return Ok(());
+    }
+
+    db.update(|db| set_leader(find_group_mut(db, id)?, character_id))
 }
 
+/// Gives `slot` to the member, the member holding it takes the previous
+/// slot of this one
+fn swap_slot(group: &mut Group, character_id: Uuid, slot: usize) -> Result<(), CommandError> {
+    if slot == 0 {
+        return Err(CommandError::Invalid("slots start at 1".to_owned()));
+    }
+
+    let current = group
+        .members
+        .iter()
+        .find(|m| m.id == character_id)
+        .ok_or_else(|| CommandError::not_found(format!("character {character_id}")))?
+        .slot;
+
+    for member in group.members.iter_mut() {
+        if member.id == character_id {
+            member.slot = slot;
+        } else if member.slot == slot {
+            member.slot = current;
+        }
+    }
+
+    Ok(())
+}
+
+/// Binds the member to the `Focus` hotkey with this number
 #[tauri::command]
-pub fn set_character_breed(name: String, breed: Breed) -> Vec<Group> {
-    let Ok(mut db) = get_database().write() else {
-        return vec![];
-    };
+pub fn set_character_slot(
+    id: Uuid,
+    character_id: Uuid,
+    slot: usize,
+) -> Result<Vec<GroupView>, CommandError> {
+    let mut db = get_database().write()?;
+
+    db.update(|db| swap_slot(find_group_mut(db, id)?, character_id, slot))?;
+
+    Ok(views(&db))
+}
+
+fn find_character_mut(db: &mut Database, id: Uuid) -> Result<&mut Character, CommandError> {
+    db.find_character_mut(id)
+        .ok_or_else(|| CommandError::not_found(format!("character {id}")))
+}
+
+#[tauri::command]
+pub fn set_character_breed(id: Uuid, breed: Breed) -> Result<Vec<GroupView>, CommandError> {
+    let mut db = get_database().write()?;
+
+    db.update(|db| {
+        find_character_mut(db, id)?.breed = Some(breed);
+        Ok(())
+    })?;
+
+    Ok(views(&db))
+}
 
-    if let Some(character) = db.characters.get_mut(&name) {
-        character.breed = Some(breed.clone());
+#[tauri::command]
+pub fn set_character_notes(id: Uuid, notes: String) -> Result<Vec<GroupView>, CommandError> {
+    let mut db = get_database().write()?;
+
+    db.update(|db| {
+        find_character_mut(db, id)?.notes = notes.trim().to_owned();
+        Ok(())
+    })?;
+
+    Ok(views(&db))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_group_names() {
+        let db = Database {
+            groups: vec![Group::new("duo"), Group::new("trio")],
+            ..Default::default()
+        };
+        let duo = db.groups[0].id;
+
+        assert_eq!(validate_name(&db, "  farm ", None).unwrap(), "farm");
+        assert_eq!(validate_name(&db, "duo", Some(duo)).unwrap(), "duo");
+        assert!(matches!(
+            validate_name(&db, " ", None),
+            Err(CommandError::Invalid(_))
+        ));
+        assert!(matches!(
+            validate_name(&db, "trio", Some(duo)),
+            Err(CommandError::Duplicate(_))
+        ));
     }
 
-    for group in db.groups.iter_mut() {
-        if let Some(character) = group.characters.iter_mut().find(|c| c.name == name) {
-            character.breed = Some(breed.clone());
-        }
+    #[test]
+    fn free_group_names() {
+        let db = Database {
+            groups: vec![Group::new("duo"), Group::new("duo (2)")],
+            ..Default::default()
+        };
+
+        assert_eq!(db.free_group_name("duo"), "duo (3)");
+        assert_eq!(db.free_group_name("trio"), "trio (2)");
     }
 
-    db.save();
-    db.groups.clone()
+    #[test]
+    fn views_resolve_members() {
+        let iop = Character::new("Iop");
+        let mut db = Database {
+            groups: vec![Group::new("duo"), Group::new("solo")],
+            characters: vec![iop.clone()],
+            ..Default::default()
+        };
+        let cra = db.character_entry(&"Cra".into()).id;
+
+        db.groups[0].members = vec![Member::new(iop.id, 1), Member::new(cra, 2)];
+        db.groups[1].members = vec![
+            Member {
+                enabled: false,
+                ..Member::new(iop.id, 1)
+            },
+            // Members whose character is gone are skipped
+            Member::new(Uuid::new_v4(), 2),
+        ];
+        db.find_character_mut(iop.id).unwrap().breed = Some(Breed::Iop);
+
+        let views = views(&db);
+
+        assert_eq!(views[0].characters.len(), 2);
+        assert_eq!(views[0].characters[1].character.name, "Cra");
+        assert_eq!(views[1].characters.len(), 1);
+        assert!(!views[1].characters[0].enabled);
+        // The breed is shared by every group
+        assert_eq!(views[0].characters[0].character.breed, Some(Breed::Iop));
+        assert_eq!(views[1].characters[0].character.breed, Some(Breed::Iop));
+    }
+
+    #[test]
+    fn swap_member_slots() {
+        let (iop, cra) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut group = Group::new("duo");
+        group.members = vec![Member::new(iop, 1), Member::new(cra, 2)];
+
+        assert_eq!(group.free_slot(), 3);
+
+        swap_slot(&mut group, iop, 4).unwrap();
+        assert_eq!(group.free_slot(), 1);
+
+        swap_slot(&mut group, cra, 4).unwrap();
+        assert_eq!(group.members[0].slot, 2);
+        assert_eq!(group.members[1].slot, 4);
+
+        assert!(swap_slot(&mut group, iop, 0).is_err());
+        assert!(swap_slot(&mut group, Uuid::new_v4(), 1).is_err());
+    }
+
+    #[test]
+    fn leader_defaults_to_the_first_member() {
+        let (iop, cra) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut group = Group::new("duo");
+        assert_eq!(group.leader(), None);
+
+        group.members = vec![Member::new(iop, 1), Member::new(cra, 2)];
+        assert_eq!(group.leader(), Some(iop));
+
+        set_leader(&mut group, cra).unwrap();
+        assert_eq!(group.leader(), Some(cra));
+        assert!(set_leader(&mut group, Uuid::new_v4()).is_err());
+
+        // A leader that left the group is ignored
+        group.members.remove(1);
+        assert_eq!(group.leader(), Some(iop));
+    }
 }
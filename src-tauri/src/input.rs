@@ -0,0 +1,118 @@
+use anyhow::Result;
+use arboard::Clipboard;
+use enigo::{Button, Direction, Enigo, Key, Keyboard, Mouse};
+
+use crate::desktop::{self, Backend};
+
+/// Injects keyboard and mouse events into the focused window and gives access
+/// to the clipboard used to paste chat commands.
+pub trait Input {
+    fn key(&mut self, key: Key, direction: Direction) -> Result<()>;
+
+    fn button(&mut self, button: Button, direction: Direction) -> Result<()>;
+
+    fn text(&mut self, text: &str) -> Result<()>;
+
+    fn get_clipboard(&mut self) -> Result<String>;
+
+    fn set_clipboard(&mut self, text: &str) -> Result<()>;
+}
+
+pub fn connect() -> Result<Box<dyn Input>> {
+    match desktop::get_backend() {
+        Backend::X11 => Ok(Box::new(EnigoInput::new()?)),
+        Backend::Fake => Ok(Box::new(FakeInput::new())),
+    }
+}
+
+pub struct EnigoInput {
+    enigo: Enigo,
+    clipboard: Option<Clipboard>,
+}
+
+impl EnigoInput {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            enigo: Enigo::new(&enigo::Settings::default())?,
+            clipboard: None,
+        })
+    }
+
+    fn clipboard(&mut self) -> Result<&mut Clipboard> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(Clipboard::new()?);
+        }
+
+        Ok(self.clipboard.as_mut().expect("clipboard is initialized"))
+    }
+}
+
+impl Input for EnigoInput {
+    fn key(&mut self, key: Key, direction: Direction) -> Result<()> {
+        Ok(self.enigo.key(key, direction)?)
+    }
+
+    fn button(&mut self, button: Button, direction: Direction) -> Result<()> {
+        Ok(self.enigo.button(button, direction)?)
+    }
+
+    fn text(&mut self, text: &str) -> Result<()> {
+        Ok(self.enigo.text(text)?)
+    }
+
+    fn get_clipboard(&mut self) -> Result<String> {
+        Ok(self.clipboard()?.get_text()?)
+    }
+
+    fn set_clipboard(&mut self, text: &str) -> Result<()> {
+        Ok(self.clipboard()?.set_text(text)?)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(Key, Direction),
+    Button(Button, Direction),
+    Text(String),
+    SetClipboard(String),
+}
+
+/// Records every injected event instead of sending it
+#[derive(Debug, Default)]
+pub struct FakeInput {
+    pub events: Vec<InputEvent>,
+    pub clipboard: String,
+}
+
+impl FakeInput {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Input for FakeInput {
+    fn key(&mut self, key: Key, direction: Direction) -> Result<()> {
+        self.events.push(InputEvent::Key(key, direction));
+        Ok(())
+    }
+
+    fn button(&mut self, button: Button, direction: Direction) -> Result<()> {
+        self.events.push(InputEvent::Button(button, direction));
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<()> {
+        self.events.push(InputEvent::Text(text.to_owned()));
+        Ok(())
+    }
+
+    fn get_clipboard(&mut self) -> Result<String> {
+        Ok(self.clipboard.clone())
+    }
+
+    fn set_clipboard(&mut self, text: &str) -> Result<()> {
+        self.clipboard = text.to_owned();
+        self.events.push(InputEvent::SetClipboard(text.to_owned()));
+        Ok(())
+    }
+}
@@ -0,0 +1,526 @@
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Error, Result};
+use rdev::Key;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::{
+    database::get_database,
+    error::CommandError,
+    macros::{find_macro, Macro},
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Focus the member of the active group in slot n, or the n-th online
+    /// one with `SlotMode::Compact`, starting at 1
+    Focus(usize),
+    Next,
+    Previous,
+    Travel,
+    TravelFollowers,
+    Invite,
+    RightClick,
+    RightClickFollowers,
+    LeftClick,
+    LeftClickFollowers,
+    DoubleClick,
+    DoubleClickFollowers,
+    NextGroup,
+    PreviousGroup,
+    /// Makes the character of the focused window the leader of the active
+    /// group
+    PromoteLeader,
+    /// Cancels the running hotkey, during a wait or between two of its
+    /// steps, and the waiting ones
+    Abort,
+    /// Plays the macro with this name
+    Macro(String),
+}
+
+impl Action {
+    /// The macro played by the action, travels, invites and clicks play the
+    /// macro named after them
+    pub fn macro_name(&self) -> Option<&str> {
+        match self {
+            Action::Travel => Some("Travel"),
+            Action::TravelFollowers => Some("TravelFollowers"),
+            Action::Invite => Some("Invite"),
+            Action::RightClick => Some("RightClick"),
+            Action::RightClickFollowers => Some("RightClickFollowers"),
+            Action::LeftClick => Some("LeftClick"),
+            Action::LeftClickFollowers => Some("LeftClickFollowers"),
+            Action::DoubleClick => Some("DoubleClick"),
+            Action::DoubleClickFollowers => Some("DoubleClickFollowers"),
+            Action::Macro(name) => Some(name),
+            Action::Focus(_)
+            | Action::Next
+            | Action::Previous
+            | Action::NextGroup
+            | Action::PreviousGroup
+            | Action::PromoteLeader
+            | Action::Abort => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+    Meta,
+    /// Any other key held down, e.g. `IntlBackslash`
+    Key(Key),
+}
+
+impl Modifier {
+    fn is_pressed(&self, pressed: &HashSet<Key>) -> bool {
+        let keys: &[Key] = match self {
+            Modifier::Ctrl => &[Key::ControlLeft, Key::ControlRight],
+            Modifier::Shift => &[Key::ShiftLeft, Key::ShiftRight],
+            Modifier::Alt => &[Key::Alt, Key::AltGr],
+            Modifier::Meta => &[Key::MetaLeft, Key::MetaRight],
+            Modifier::Key(key) => std::slice::from_ref(key),
+        };
+
+        keys.iter().any(|key| pressed.contains(key))
+    }
+}
+
+impl FromStr for Modifier {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let modifier = match s.to_lowercase().as_str() {
+            "ctrl" | "control" => Modifier::Ctrl,
+            "shift" => Modifier::Shift,
+            "alt" => Modifier::Alt,
+            "meta" | "super" | "win" => Modifier::Meta,
+            _ => Modifier::Key(parse_key(s)?),
+        };
+
+        Ok(modifier)
+    }
+}
+
+impl fmt::Display for Modifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Modifier::Ctrl => write!(f, "Ctrl"),
+            Modifier::Shift => write!(f, "Shift"),
+            Modifier::Alt => write!(f, "Alt"),
+            Modifier::Meta => write!(f, "Meta"),
+            Modifier::Key(key) => write!(f, "{key:?}"),
+        }
+    }
+}
+
+/// A key combination such as `Ctrl+Shift+F3` or `IntlBackslash+PageUp`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chord {
+    pub modifiers: Vec<Modifier>,
+    pub key: Key,
+}
+
+impl Chord {
+    pub fn new(key: Key) -> Self {
+        Self {
+            modifiers: vec![],
+            key,
+        }
+    }
+
+    pub fn with(mut self, modifier: Modifier) -> Self {
+        self.modifiers.push(modifier);
+        self
+    }
+
+    pub fn matches(&self, key: Key, pressed: &HashSet<Key>) -> bool {
+        self.key == key && self.modifiers.iter().all(|m| m.is_pressed(pressed))
+    }
+
+    /// Whether both chords are triggered by the same keys, whatever the order
+    /// of their modifiers
+    pub fn overlaps(&self, other: &Chord) -> bool {
+        let contains = |a: &Chord, b: &Chord| a.modifiers.iter().all(|m| b.modifiers.contains(m));
+
+        self.key == other.key && contains(self, other) && contains(other, self)
+    }
+}
+
+impl FromStr for Chord {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+
+        let Some(key) = parts.pop() else {
+            bail!("empty chord");
+        };
+
+        if key.is_empty() || parts.iter().any(|p| p.is_empty()) {
+            bail!("invalid chord \"{s}\"");
+        }
+
+        let modifiers = parts
+            .into_iter()
+            .map(Modifier::from_str)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            modifiers,
+            key: parse_key(key)?,
+        })
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for modifier in self.modifiers.iter() {
+            write!(f, "{modifier}+")?;
+        }
+
+        write!(f, "{:?}", self.key)
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    #[serde_as(as = "DisplayFromStr")]
+    pub chord: Chord,
+    pub action: Action,
+}
+
+impl Binding {
+    pub fn new(chord: Chord, action: Action) -> Self {
+        Self { chord, action }
+    }
+}
+
+pub fn default_bindings() -> Vec<Binding> {
+    let alt = Modifier::Key(Key::IntlBackslash);
+    let slots = [
+        Key::F1,
+        Key::F2,
+        Key::F3,
+        Key::F4,
+        Key::F5,
+        Key::F6,
+        Key::F7,
+        Key::F8,
+    ];
+
+    let mut bindings = slots
+        .into_iter()
+        .enumerate()
+        .map(|(i, key)| Binding::new(Chord::new(key), Action::Focus(i + 1)))
+        .collect::<Vec<_>>();
+
+    bindings.extend([
+        Binding::new(Chord::new(Key::Tab), Action::Next),
+        Binding::new(Chord::new(Key::Tab).with(alt), Action::Previous),
+        Binding::new(Chord::new(Key::PageUp), Action::Travel),
+        Binding::new(Chord::new(Key::PageUp).with(alt), Action::TravelFollowers),
+        Binding::new(Chord::new(Key::Home), Action::Invite),
+        Binding::new(Chord::new(Key::Home).with(alt), Action::PromoteLeader),
+        Binding::new(Chord::new(Key::End), Action::RightClick),
+        Binding::new(Chord::new(Key::End).with(alt), Action::RightClickFollowers),
+        Binding::new(Chord::new(Key::Delete), Action::LeftClick),
+        Binding::new(
+            Chord::new(Key::Delete).with(alt),
+            Action::LeftClickFollowers,
+        ),
+        Binding::new(Chord::new(Key::PageDown), Action::DoubleClick),
+        Binding::new(
+            Chord::new(Key::PageDown).with(alt),
+            Action::DoubleClickFollowers,
+        ),
+        Binding::new(Chord::new(Key::Tab).with(Modifier::Ctrl), Action::NextGroup),
+        Binding::new(
+            Chord::new(Key::Tab).with(Modifier::Ctrl).with(alt),
+            Action::PreviousGroup,
+        ),
+        // The game still gets the key, Escape would close its windows
+        Binding::new(Chord::new(Key::Pause), Action::Abort),
+    ]);
+
+    bindings
+}
+
+/// Finds the binding triggered by `key`, preferring the chord with the most
+/// modifiers so that `IntlBackslash+Tab` wins over `Tab`.
+pub fn find_action<'a>(
+    bindings: &'a [Binding],
+    key: Key,
+    pressed: &HashSet<Key>,
+) -> Option<&'a Action> {
+    bindings
+        .iter()
+        .filter(|b| b.chord.matches(key, pressed))
+        .max_by_key(|b| b.chord.modifiers.len())
+        .map(|b| &b.action)
+}
+
+static KEYS: &[Key] = &[
+    Key::Alt,
+    Key::AltGr,
+    Key::Backspace,
+    Key::CapsLock,
+    Key::ControlLeft,
+    Key::ControlRight,
+    Key::Delete,
+    Key::DownArrow,
+    Key::End,
+    Key::Escape,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::Home,
+    Key::LeftArrow,
+    Key::MetaLeft,
+    Key::MetaRight,
+    Key::PageDown,
+    Key::PageUp,
+    Key::Return,
+    Key::RightArrow,
+    Key::ShiftLeft,
+    Key::ShiftRight,
+    Key::Space,
+    Key::Tab,
+    Key::UpArrow,
+    Key::PrintScreen,
+    Key::ScrollLock,
+    Key::Pause,
+    Key::NumLock,
+    Key::BackQuote,
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+    Key::Num0,
+    Key::Minus,
+    Key::Equal,
+    Key::KeyQ,
+    Key::KeyW,
+    Key::KeyE,
+    Key::KeyR,
+    Key::KeyT,
+    Key::KeyY,
+    Key::KeyU,
+    Key::KeyI,
+    Key::KeyO,
+    Key::KeyP,
+    Key::LeftBracket,
+    Key::RightBracket,
+    Key::KeyA,
+    Key::KeyS,
+    Key::KeyD,
+    Key::KeyF,
+    Key::KeyG,
+    Key::KeyH,
+    Key::KeyJ,
+    Key::KeyK,
+    Key::KeyL,
+    Key::SemiColon,
+    Key::Quote,
+    Key::BackSlash,
+    Key::IntlBackslash,
+    Key::KeyZ,
+    Key::KeyX,
+    Key::KeyC,
+    Key::KeyV,
+    Key::KeyB,
+    Key::KeyN,
+    Key::KeyM,
+    Key::Comma,
+    Key::Dot,
+    Key::Slash,
+    Key::Insert,
+    Key::KpReturn,
+    Key::KpMinus,
+    Key::KpPlus,
+    Key::KpMultiply,
+    Key::KpDivide,
+    Key::Kp0,
+    Key::Kp1,
+    Key::Kp2,
+    Key::Kp3,
+    Key::Kp4,
+    Key::Kp5,
+    Key::Kp6,
+    Key::Kp7,
+    Key::Kp8,
+    Key::Kp9,
+    Key::KpDelete,
+    Key::Function,
+];
+
+/// Parses a key by its `rdev::Key` name (case insensitive), a single letter
+/// (`A` for `KeyA`) or a single digit (`1` for `Num1`).
+pub fn parse_key(s: &str) -> Result<Key> {
+    let name = match s.len() {
+        1 if s.chars().all(|c| c.is_ascii_alphabetic()) => format!("Key{s}"),
+        1 if s.chars().all(|c| c.is_ascii_digit()) => format!("Num{s}"),
+        _ => s.to_owned(),
+    };
+
+    KEYS.iter()
+        .find(|key| format!("{key:?}").eq_ignore_ascii_case(&name))
+        .copied()
+        .ok_or(anyhow!("unknown key \"{s}\""))
+}
+
+#[tauri::command]
+pub fn get_keymap() -> Result<Vec<Binding>, CommandError> {
+    let db = get_database().read()?;
+
+    Ok(db.keymap.clone())
+}
+
+/// Rejects the bindings no hotkey could ever trigger or play
+pub(crate) fn validate(bindings: &[Binding], macros: &[Macro]) -> Result<(), CommandError> {
+    for (i, binding) in bindings.iter().enumerate() {
+        match &binding.action {
+            Action::Focus(0) => {
+                return Err(CommandError::Invalid("slots start at 1".to_owned()));
+            }
+            Action::Macro(name) if find_macro(macros, name).is_none() => {
+                return Err(CommandError::not_found(format!("macro {name}")));
+            }
+            _ => {}
+        }
+
+        if bindings[..i]
+            .iter()
+            .any(|b| b.chord.overlaps(&binding.chord))
+        {
+            return Err(CommandError::duplicate(format!("chord {}", binding.chord)));
+        }
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_keymap(bindings: Vec<Binding>) -> Result<Vec<Binding>, CommandError> {
+    let mut db = get_database().write()?;
+
+    validate(&bindings, &db.macros)?;
+
+    db.update(|db| {
+        db.keymap = bindings;
+        Ok(())
+    })?;
+
+    Ok(db.keymap.clone())
+}
+
+#[tauri::command]
+pub fn reset_keymap() -> Result<Vec<Binding>, CommandError> {
+    set_keymap(default_bindings())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_chord() {
+        let chord = "Ctrl+Shift+F3".parse::<Chord>().unwrap();
+
+        assert_eq!(
+            chord,
+            Chord::new(Key::F3)
+                .with(Modifier::Ctrl)
+                .with(Modifier::Shift)
+        );
+        assert_eq!(chord.to_string(), "Ctrl+Shift+F3");
+
+        assert_eq!(
+            "intlbackslash + a".parse::<Chord>().unwrap(),
+            Chord::new(Key::KeyA).with(Modifier::Key(Key::IntlBackslash))
+        );
+        assert_eq!("1".parse::<Chord>().unwrap(), Chord::new(Key::Num1));
+    }
+
+    #[test]
+    fn parse_invalid_chord() {
+        assert!("".parse::<Chord>().is_err());
+        assert!("Ctrl+".parse::<Chord>().is_err());
+        assert!("Hyper+F1".parse::<Chord>().is_err());
+    }
+
+    #[test]
+    fn find_action_prefers_most_modifiers() {
+        let bindings = default_bindings();
+        let mut pressed = HashSet::from([Key::Tab]);
+
+        assert_eq!(
+            find_action(&bindings, Key::Tab, &pressed),
+            Some(&Action::Next)
+        );
+
+        pressed.insert(Key::IntlBackslash);
+
+        assert_eq!(
+            find_action(&bindings, Key::Tab, &pressed),
+            Some(&Action::Previous)
+        );
+        assert_eq!(
+            find_action(&bindings, Key::F3, &pressed),
+            Some(&Action::Focus(3))
+        );
+        assert_eq!(find_action(&bindings, Key::KeyA, &pressed), None);
+    }
+
+    #[test]
+    fn reject_invalid_bindings() {
+        let macros = vec![];
+        let binding = |chord: &str, action| Binding::new(chord.parse().unwrap(), action);
+
+        assert_eq!(validate(&default_bindings(), &macros), Ok(()));
+        assert_eq!(
+            validate(
+                &[binding("F1", Action::Macro("Travel".to_owned()))],
+                &macros
+            ),
+            Ok(())
+        );
+
+        assert!(matches!(
+            validate(&[binding("F1", Action::Focus(0))], &macros),
+            Err(CommandError::Invalid(_))
+        ));
+        assert!(matches!(
+            validate(&[binding("F1", Action::Macro("Dance".to_owned()))], &macros),
+            Err(CommandError::NotFound(_))
+        ));
+        assert!(matches!(
+            validate(
+                &[
+                    binding("Ctrl+Shift+F1", Action::Next),
+                    binding("Shift+Ctrl+F1", Action::Previous),
+                ],
+                &macros
+            ),
+            Err(CommandError::Duplicate(_))
+        ));
+    }
+}
@@ -1,27 +1,72 @@
+mod bundle;
+mod character;
+mod cli;
 mod database;
 mod desktop;
+mod error;
 mod group;
+mod input;
+mod keymap;
+mod macros;
+mod profile;
+mod queue;
+mod settings;
 mod shortcut;
 
 #[cfg(desktop)]
 mod tray;
 
+use bundle::{export_bundle, import_bundle};
+use character::{get_characters, get_stale_characters, prune_characters, set_character_archived};
+use database::{get_database_warning, reload_database, reset_database, resolve_conflict};
+use desktop::match_window;
 use group::{
-    add_character_to_group, add_character_to_group_at, create_group, delete_group,
-    get_available_characters, get_groups, remove_character_from_group, set_character_breed,
-    set_character_enabled,
+    add_character_to_group, add_character_to_group_at, create_group, delete_group, duplicate_group,
+    get_active_group, get_available_characters, get_groups, move_group,
+    remove_character_from_group, rename_group, set_active_group, set_auto_group,
+    set_character_breed, set_character_enabled, set_character_notes, set_character_slot,
+    set_group_leader,
 };
+use keymap::{get_keymap, reset_keymap, set_keymap};
+use macros::{get_macros, reset_macros, set_macros};
+use profile::{get_profile, get_profiles, set_profile};
+use settings::{get_settings, set_settings};
 use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let args = cli::Args::from_env();
+
+    if let Some(dir) = args.data_dir {
+        profile::set_data_dir(dir);
+    }
+
+    if let Some(name) = args.profile {
+        if let Err(e) = profile::init_profile(name) {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    }
+
+    if let Some(command) = args.command {
+        if let Err(e) = cli::run(command) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    desktop::set_backend(desktop::Backend::from_env());
     desktop::watch();
     shortcut::watch();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .setup(|app| {
-            #[cfg(all(desktop))]
+            database::watch(app.handle().clone());
+
+            #[cfg(desktop)]
             {
                 let handle = app.handle();
                 tray::create_tray(handle)?;
@@ -29,36 +74,65 @@ pub fn run() {
 
             Ok(())
         })
-        .on_window_event(|window, event| match event {
-            tauri::WindowEvent::CloseRequested { api, .. } => {
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
                 api.prevent_close();
                 window
                     .hide()
                     .expect("failed to hide main window at WindowEvent.CloseRequested");
             }
-            _ => {}
         })
         .invoke_handler(tauri::generate_handler![
             get_groups,
             create_group,
             delete_group,
+            rename_group,
+            duplicate_group,
+            move_group,
             get_available_characters,
             remove_character_from_group,
             add_character_to_group,
             add_character_to_group_at,
             set_character_enabled,
+            set_character_slot,
+            set_group_leader,
             set_character_breed,
+            set_character_notes,
+            get_characters,
+            get_stale_characters,
+            prune_characters,
+            set_character_archived,
+            get_active_group,
+            set_active_group,
+            set_auto_group,
+            get_keymap,
+            set_keymap,
+            reset_keymap,
+            get_macros,
+            set_macros,
+            reset_macros,
+            get_settings,
+            match_window,
+            set_settings,
+            get_database_warning,
+            reload_database,
+            reset_database,
+            resolve_conflict,
+            export_bundle,
+            import_bundle,
+            get_profiles,
+            get_profile,
+            set_profile,
         ])
         .build(tauri::generate_context!())
         .expect("error while running tauri application")
-        .run(|app_handle, event| match event {
-            tauri::RunEvent::Ready => {
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Ready = event {
                 let window = app_handle
                     .get_webview_window("main")
                     .expect("failed to get main window");
 
                 window.hide().expect("failed to hide main window at ready");
             }
-            _ => {}
         });
 }
@@ -0,0 +1,402 @@
+use std::{
+    process::Command,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use enigo::{Button, Direction, Key};
+
+use super::plan::Op;
+use crate::{desktop::Desktop, input::Input, settings::Settings};
+
+/// Runs the plans of the hotkeys
+pub trait Executor {
+    /// Reads the clipboard when a macro depends on it
+    fn get_clipboard(&mut self) -> Result<String>;
+
+    fn execute(&mut self, plan: &[Op]) -> Result<()>;
+}
+
+/// Sends the plans to the desktop. Keys, buttons and texts that fail are
+/// ignored, a failed clipboard write or required focus stops the plan, and
+/// so does `cancelled` before each operation and during waits. Whoever sets
+/// it unparks the thread running the plan. Keys and buttons still pressed
+/// when a plan stops are released
+pub struct LiveExecutor<'a> {
+    pub desktop: &'a dyn Desktop,
+    pub input: &'a mut dyn Input,
+    pub settings: &'a Settings,
+    pub cancelled: &'a AtomicBool,
+}
+
+impl Executor for LiveExecutor<'_> {
+    fn get_clipboard(&mut self) -> Result<String> {
+        self.input.get_clipboard()
+    }
+
+    fn execute(&mut self, plan: &[Op]) -> Result<()> {
+        let mut held = vec![];
+        let result = self.run(plan, &mut held);
+
+        // They would stay down in the session of the user, e.g. Control in
+        // the middle of a paste
+        for pressed in held.into_iter().rev() {
+            let _ = match pressed {
+                Pressed::Key(key) => self.input.key(key, Direction::Release),
+                Pressed::Button(button) => self.input.button(button, Direction::Release),
+            };
+        }
+
+        result
+    }
+}
+
+/// A key or button sent with `Direction::Press` and not released yet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pressed {
+    Key(Key),
+    Button(Button),
+}
+
+/// Tracks what `direction` leaves pressed
+fn hold(held: &mut Vec<Pressed>, pressed: Pressed, direction: Direction) {
+    match direction {
+        Direction::Press if !held.contains(&pressed) => held.push(pressed),
+        Direction::Release => held.retain(|p| *p != pressed),
+        _ => {}
+    }
+}
+
+impl LiveExecutor<'_> {
+    fn run(&mut self, plan: &[Op], held: &mut Vec<Pressed>) -> Result<()> {
+        let mut skipping = false;
+
+        for op in plan {
+            if self.cancelled.load(Ordering::SeqCst) {
+                bail!("cancelled");
+            }
+
+            if let Op::Focus { window, optional } = op {
+                let shown = self.desktop.show_window(
+                    *window,
+                    self.settings.focus_strategy,
+                    self.settings.focus_timeout(),
+                    self.cancelled,
+                );
+
+                match shown {
+                    Ok(()) => skipping = false,
+                    Err(_) if *optional => skipping = true,
+                    Err(e) => return Err(e.into()),
+                }
+
+                continue;
+            }
+
+            if let Op::EndIteration = op {
+                skipping = false;
+                continue;
+            }
+
+            if skipping {
+                continue;
+            }
+
+            match op {
+                Op::Focus { .. } | Op::EndIteration => {}
+                Op::Key(key, direction) => {
+                    if self.input.key(*key, *direction).is_ok() {
+                        hold(held, Pressed::Key(*key), *direction);
+                    }
+                }
+                Op::Button(button, direction) => {
+                    if self.input.button(*button, *direction).is_ok() {
+                        hold(held, Pressed::Button(*button), *direction);
+                    }
+                }
+                Op::Text(text) => {
+                    let _ = self.input.text(text);
+                }
+                Op::SetClipboard(text) => self.input.set_clipboard(text)?,
+                Op::Wait(ms) => self.wait(Duration::from_millis(*ms))?,
+                Op::Notify(message) => notify(message),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn wait(&self, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+
+        loop {
+            if self.cancelled.load(Ordering::SeqCst) {
+                bail!("cancelled");
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                return Ok(());
+            }
+
+            thread::park_timeout(remaining);
+        }
+    }
+}
+
+/// Desktop notification through `notify-send`, failures are logged since
+/// nobody waits for them, e.g. when it is not installed
+fn notify(message: &str) {
+    let message = message.to_owned();
+
+    thread::spawn(move || {
+        let status = Command::new("notify-send")
+            .args(["--app-name=Dofus Switcher", "Dofus Switcher", &message])
+            .status();
+
+        match status {
+            Ok(status) if !status.success() => eprintln!("notify-send failed: {status}"),
+            Ok(_) => {}
+            Err(e) => eprintln!("cannot run notify-send: {e}"),
+        }
+    });
+}
+
+/// Logs the plans to stderr instead of running them, selected by setting
+/// `DOFUS_SWITCHER_DRY_RUN`
+#[derive(Debug, Default)]
+pub struct DryRun {
+    /// Given to the macros reading the clipboard
+    pub clipboard: String,
+    pub log: Vec<String>,
+}
+
+impl DryRun {
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os("DOFUS_SWITCHER_DRY_RUN")
+            .filter(|value| !value.is_empty())
+            .map(|_| Self::default())
+    }
+}
+
+impl Executor for DryRun {
+    fn get_clipboard(&mut self) -> Result<String> {
+        Ok(self.clipboard.clone())
+    }
+
+    fn execute(&mut self, plan: &[Op]) -> Result<()> {
+        for op in plan {
+            let line = op.to_string();
+
+            eprintln!("dry run: {line}");
+            self.log.push(line);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        desktop::FakeDesktop,
+        input::{FakeInput, InputEvent},
+    };
+
+    #[test]
+    fn skip_after_optional_focus_failures() {
+        let desktop = FakeDesktop::new();
+        let iop = desktop.open_window("Iop");
+        let cra = desktop.open_window("Cra");
+        desktop.refuse_focus(cra);
+
+        let mut input = FakeInput::new();
+        let settings = Settings::default();
+        let mut executor = LiveExecutor {
+            desktop: &desktop,
+            input: &mut input,
+            settings: &settings,
+            cancelled: &AtomicBool::new(false),
+        };
+
+        let plan = [
+            Op::Focus {
+                window: cra,
+                optional: true,
+            },
+            Op::Text("skipped".to_owned()),
+            Op::Focus {
+                window: iop,
+                optional: true,
+            },
+            Op::Key(Key::Return, Direction::Click),
+        ];
+        executor.execute(&plan).unwrap();
+
+        let required = [Op::Focus {
+            window: cra,
+            optional: false,
+        }];
+        assert!(executor.execute(&required).is_err());
+
+        assert_eq!(desktop.shown_windows(), vec![iop]);
+        assert_eq!(
+            input.events,
+            vec![InputEvent::Key(Key::Return, Direction::Click)]
+        );
+    }
+
+    #[test]
+    fn run_the_steps_after_a_loop() {
+        let desktop = FakeDesktop::new();
+        let iop = desktop.open_window("Iop");
+        desktop.refuse_focus(iop);
+
+        let mut input = FakeInput::new();
+        let settings = Settings::default();
+        let mut executor = LiveExecutor {
+            desktop: &desktop,
+            input: &mut input,
+            settings: &settings,
+            cancelled: &AtomicBool::new(false),
+        };
+
+        let plan = [
+            Op::Focus {
+                window: iop,
+                optional: true,
+            },
+            Op::Text("skipped".to_owned()),
+            Op::EndIteration,
+            Op::Key(Key::Return, Direction::Click),
+        ];
+        executor.execute(&plan).unwrap();
+
+        assert_eq!(
+            input.events,
+            vec![InputEvent::Key(Key::Return, Direction::Click)]
+        );
+    }
+
+    #[test]
+    fn stop_when_cancelled() {
+        let desktop = FakeDesktop::new();
+        let mut input = FakeInput::new();
+        let settings = Settings::default();
+        let cancelled = AtomicBool::new(true);
+        let mut executor = LiveExecutor {
+            desktop: &desktop,
+            input: &mut input,
+            settings: &settings,
+            cancelled: &cancelled,
+        };
+
+        let plan = [Op::Key(Key::Return, Direction::Click)];
+        assert!(executor.execute(&plan).is_err());
+
+        cancelled.store(false, Ordering::SeqCst);
+        executor.execute(&plan).unwrap();
+
+        assert_eq!(
+            input.events,
+            vec![InputEvent::Key(Key::Return, Direction::Click)]
+        );
+    }
+
+    #[test]
+    fn abort_during_waits() {
+        let desktop = FakeDesktop::new();
+        let mut input = FakeInput::new();
+        let settings = Settings::default();
+        let cancelled = AtomicBool::new(false);
+        let worker = thread::current();
+        let start = Instant::now();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                cancelled.store(true, Ordering::SeqCst);
+                worker.unpark();
+            });
+
+            let mut executor = LiveExecutor {
+                desktop: &desktop,
+                input: &mut input,
+                settings: &settings,
+                cancelled: &cancelled,
+            };
+
+            let plan = [Op::Wait(10_000), Op::Key(Key::Return, Direction::Click)];
+            assert!(executor.execute(&plan).is_err());
+        });
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(input.events.is_empty());
+    }
+
+    #[test]
+    fn release_keys_when_cancelled() {
+        let desktop = FakeDesktop::new();
+        let mut input = FakeInput::new();
+        let settings = Settings::default();
+        let cancelled = AtomicBool::new(false);
+        let worker = thread::current();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                cancelled.store(true, Ordering::SeqCst);
+                worker.unpark();
+            });
+
+            let mut executor = LiveExecutor {
+                desktop: &desktop,
+                input: &mut input,
+                settings: &settings,
+                cancelled: &cancelled,
+            };
+
+            let plan = [
+                Op::Key(Key::Control, Direction::Press),
+                Op::Button(Button::Left, Direction::Press),
+                Op::Wait(10_000),
+                Op::Key(Key::Unicode('v'), Direction::Click),
+                Op::Button(Button::Left, Direction::Release),
+                Op::Key(Key::Control, Direction::Release),
+            ];
+            assert!(executor.execute(&plan).is_err());
+        });
+
+        assert_eq!(
+            input.events,
+            vec![
+                InputEvent::Key(Key::Control, Direction::Press),
+                InputEvent::Button(Button::Left, Direction::Press),
+                InputEvent::Button(Button::Left, Direction::Release),
+                InputEvent::Key(Key::Control, Direction::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn dry_run_logs_plans() {
+        let mut executor = DryRun::default();
+
+        executor
+            .execute(&[
+                Op::Button(Button::Middle, Direction::Click),
+                Op::Notify("Cra is offline".to_owned()),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            executor.log,
+            vec!["button Middle Click", "notify \"Cra is offline\""]
+        );
+    }
+}
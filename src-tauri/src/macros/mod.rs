@@ -0,0 +1,432 @@
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use lazy_regex::{regex_replace_all, Regex};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::{database::get_database, error::CommandError};
+
+mod executor;
+mod plan;
+
+pub use executor::{DryRun, Executor, LiveExecutor};
+pub use plan::{focus, plan, Op, Team};
+
+/// Steps played by a hotkey in the windows of the active group
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Macro {
+    pub name: String,
+    /// Regex run on the clipboard first, the macro does nothing when it does
+    /// not match. Its named groups are available as `{name}` in texts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clipboard: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "step")]
+pub enum Step {
+    /// Runs `steps` for each online character of the group, leader first.
+    /// Characters whose window cannot be focused are skipped
+    ForEach {
+        include_leader: bool,
+        /// Focuses the window of the character before its steps, they run
+        /// in the current window otherwise
+        focus: bool,
+        steps: Vec<Step>,
+    },
+    /// Focuses the window of the leader again
+    FocusLeader,
+    /// Types the text, `{character}` is the name of the current character
+    /// in `ForEach`
+    TypeText {
+        text: String,
+    },
+    /// Puts the text in the clipboard, with the same replacements as
+    /// `TypeText`
+    Copy {
+        text: String,
+    },
+    /// Replaces the content of the chat input with the clipboard
+    Paste,
+    Key {
+        #[serde_as(as = "DisplayFromStr")]
+        key: KeyName,
+        #[serde(default)]
+        direction: KeyDirection,
+    },
+    Click {
+        button: MouseButton,
+        count: usize,
+    },
+    /// Milliseconds
+    Wait {
+        ms: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyDirection {
+    #[default]
+    Click,
+    Press,
+    Release,
+}
+
+impl From<KeyDirection> for enigo::Direction {
+    fn from(direction: KeyDirection) -> Self {
+        match direction {
+            KeyDirection::Click => enigo::Direction::Click,
+            KeyDirection::Press => enigo::Direction::Press,
+            KeyDirection::Release => enigo::Direction::Release,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl From<MouseButton> for enigo::Button {
+    fn from(button: MouseButton) -> Self {
+        match button {
+            MouseButton::Left => enigo::Button::Left,
+            MouseButton::Right => enigo::Button::Right,
+            MouseButton::Middle => enigo::Button::Middle,
+        }
+    }
+}
+
+/// A key sent by a macro, `Return`, `Space`, `Control`... or a single
+/// character
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyName(pub enigo::Key);
+
+static NAMED_KEYS: &[(&str, enigo::Key)] = &[
+    ("Return", enigo::Key::Return),
+    ("Space", enigo::Key::Space),
+    ("Escape", enigo::Key::Escape),
+    ("Tab", enigo::Key::Tab),
+    ("Backspace", enigo::Key::Backspace),
+    ("Delete", enigo::Key::Delete),
+    ("Control", enigo::Key::Control),
+    ("Shift", enigo::Key::Shift),
+    ("Alt", enigo::Key::Alt),
+    ("UpArrow", enigo::Key::UpArrow),
+    ("DownArrow", enigo::Key::DownArrow),
+    ("LeftArrow", enigo::Key::LeftArrow),
+    ("RightArrow", enigo::Key::RightArrow),
+];
+
+impl FromStr for KeyName {
+    type Err = CommandError;
+
+    fn from_str(s: &str) -> Result<Self, CommandError> {
+        let mut chars = s.chars();
+
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(KeyName(enigo::Key::Unicode(c)));
+        }
+
+        NAMED_KEYS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, key)| KeyName(*key))
+            .ok_or_else(|| CommandError::Invalid(format!("unknown key \"{s}\"")))
+    }
+}
+
+impl fmt::Display for KeyName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let enigo::Key::Unicode(c) = self.0 {
+            return write!(f, "{c}");
+        }
+
+        match NAMED_KEYS.iter().find(|(_, key)| *key == self.0) {
+            Some((name, _)) => write!(f, "{name}"),
+            None => write!(f, "{:?}", self.0),
+        }
+    }
+}
+
+impl Macro {
+    /// Checks the clipboard regex, and that `ForEach` steps are not nested
+    pub fn validate(&self) -> Result<(), CommandError> {
+        if self.name.trim().is_empty() {
+            return Err(CommandError::Invalid(
+                "macro names cannot be empty".to_owned(),
+            ));
+        }
+
+        self.clipboard_regex()?;
+
+        for step in self.steps.iter() {
+            if let Step::ForEach { steps, .. } = step {
+                if steps.iter().any(|s| matches!(s, Step::ForEach { .. })) {
+                    return Err(CommandError::Invalid(format!(
+                        "macro {} nests ForEach steps",
+                        self.name
+                    )));
+                }
+
+                // The iteration would lose the window of its character
+                if steps.iter().any(|s| matches!(s, Step::FocusLeader)) {
+                    return Err(CommandError::Invalid(format!(
+                        "macro {} focuses the leader inside a ForEach step",
+                        self.name
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the steps focus the leader or go through the characters,
+    /// which starts or skips the leader. `validate` keeps `FocusLeader` out
+    /// of `ForEach`, only the top level is checked
+    pub fn needs_leader(&self) -> bool {
+        self.steps
+            .iter()
+            .any(|s| matches!(s, Step::ForEach { .. } | Step::FocusLeader))
+    }
+
+    pub fn clipboard_regex(&self) -> Result<Option<Regex>, CommandError> {
+        self.clipboard
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| CommandError::Invalid(format!("invalid clipboard regex: {e}")))
+    }
+}
+
+/// Replaces `{name}` by the value of `name`, unknown names are kept. Values
+/// are not expanded in turn, e.g. a clipboard holding `{character}`
+pub fn expand(text: &str, vars: &[(String, String)]) -> String {
+    regex_replace_all!(r"\{(\w+)\}", text, |whole: &str, name: &str| {
+        vars.iter()
+            .find(|(n, _)| n == name)
+            .map_or(whole, |(_, value)| value.as_str())
+            .to_owned()
+    })
+    .into_owned()
+}
+
+fn wait(ms: u64) -> Step {
+    Step::Wait { ms }
+}
+
+fn key(key: enigo::Key) -> Step {
+    Step::Key {
+        key: KeyName(key),
+        direction: KeyDirection::Click,
+    }
+}
+
+fn travel(name: &str, include_leader: bool) -> Macro {
+    Macro {
+        name: name.to_owned(),
+        clipboard: Some(r"(?P<x>-?\d+),\s*(?P<y>-?\d+)".to_owned()),
+        steps: vec![
+            Step::Copy {
+                text: "/travel {x},{y}".to_owned(),
+            },
+            Step::ForEach {
+                include_leader,
+                focus: true,
+                steps: vec![
+                    wait(40),
+                    key(enigo::Key::Space),
+                    wait(40),
+                    Step::Paste,
+                    wait(40),
+                    key(enigo::Key::Return),
+                    wait(200),
+                    key(enigo::Key::Return),
+                    wait(100),
+                ],
+            },
+            Step::FocusLeader,
+        ],
+    }
+}
+
+fn click(name: &str, button: MouseButton, count: usize, include_leader: bool) -> Macro {
+    Macro {
+        name: name.to_owned(),
+        clipboard: None,
+        steps: vec![
+            Step::ForEach {
+                include_leader,
+                focus: true,
+                steps: vec![Step::Click { button, count }, wait(150)],
+            },
+            Step::FocusLeader,
+        ],
+    }
+}
+
+/// The travel, invite and click hotkeys, named after their `Action`
+pub fn default_macros() -> Vec<Macro> {
+    vec![
+        travel("Travel", true),
+        travel("TravelFollowers", false),
+        Macro {
+            name: "Invite".to_owned(),
+            clipboard: None,
+            steps: vec![
+                Step::FocusLeader,
+                wait(40),
+                key(enigo::Key::Space),
+                Step::ForEach {
+                    include_leader: false,
+                    focus: false,
+                    steps: vec![
+                        Step::Copy {
+                            text: "/invite {character}".to_owned(),
+                        },
+                        wait(40),
+                        Step::Paste,
+                        wait(40),
+                        key(enigo::Key::Return),
+                        wait(100),
+                    ],
+                },
+            ],
+        },
+        click("RightClick", MouseButton::Right, 1, true),
+        click("RightClickFollowers", MouseButton::Right, 1, false),
+        click("LeftClick", MouseButton::Left, 1, true),
+        click("LeftClickFollowers", MouseButton::Left, 1, false),
+        click("DoubleClick", MouseButton::Left, 2, true),
+        click("DoubleClickFollowers", MouseButton::Left, 2, false),
+    ]
+}
+
+/// The macro called `name`, the built-in one when the configured macros do
+/// not have it
+pub fn find_macro(macros: &[Macro], name: &str) -> Option<Macro> {
+    macros
+        .iter()
+        .find(|m| m.name == name)
+        .cloned()
+        .or_else(|| default_macros().into_iter().find(|m| m.name == name))
+}
+
+#[tauri::command]
+pub fn get_macros() -> Result<Vec<Macro>, CommandError> {
+    let db = get_database().read()?;
+
+    Ok(db.macros.clone())
+}
+
+#[tauri::command]
+pub fn set_macros(macros: Vec<Macro>) -> Result<Vec<Macro>, CommandError> {
+    let mut names = HashSet::new();
+
+    for m in macros.iter() {
+        m.validate()?;
+
+        if !names.insert(m.name.as_str()) {
+            return Err(CommandError::duplicate(format!("macro {}", m.name)));
+        }
+    }
+
+    let mut db = get_database().write()?;
+
+    db.update(|db| {
+        db.macros = macros;
+        Ok(())
+    })?;
+
+    Ok(db.macros.clone())
+}
+
+#[tauri::command]
+pub fn reset_macros() -> Result<Vec<Macro>, CommandError> {
+    set_macros(default_macros())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_macros_are_valid() {
+        for m in default_macros() {
+            assert!(m.validate().is_ok(), "{}", m.name);
+        }
+    }
+
+    #[test]
+    fn round_trip_toml() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Config {
+            macros: Vec<Macro>,
+        }
+
+        let config = Config {
+            macros: default_macros(),
+        };
+        let data = toml::to_string(&config).unwrap();
+
+        assert!(data.contains("step = \"ForEach\""));
+        assert!(data.contains("key = \"Return\""));
+        assert_eq!(toml::from_str::<Config>(&data).unwrap(), config);
+    }
+
+    #[test]
+    fn parse_key_names() {
+        assert_eq!("return".parse::<KeyName>().unwrap().0, enigo::Key::Return);
+        assert_eq!("a".parse::<KeyName>().unwrap().0, enigo::Key::Unicode('a'));
+        assert_eq!(KeyName(enigo::Key::Space).to_string(), "Space");
+        assert!("Hyper".parse::<KeyName>().is_err());
+    }
+
+    #[test]
+    fn reject_invalid_macros() {
+        let mut m = Macro {
+            name: "Broken".to_owned(),
+            clipboard: Some("(".to_owned()),
+            steps: vec![],
+        };
+        assert!(m.validate().is_err());
+
+        m.clipboard = None;
+        m.steps = vec![Step::ForEach {
+            include_leader: true,
+            focus: true,
+            steps: vec![Step::ForEach {
+                include_leader: true,
+                focus: true,
+                steps: vec![],
+            }],
+        }];
+        assert!(m.validate().is_err());
+
+        m.steps = vec![Step::ForEach {
+            include_leader: false,
+            focus: false,
+            steps: vec![Step::Paste, Step::FocusLeader],
+        }];
+        assert!(m.validate().is_err());
+    }
+
+    #[test]
+    fn expand_variables() {
+        let vars = [("x".to_owned(), "-20".to_owned())];
+
+        assert_eq!(expand("/travel {x},{y}", &vars), "/travel -20,{y}");
+
+        let vars = [
+            ("x".to_owned(), "{character}".to_owned()),
+            ("character".to_owned(), "Iop".to_owned()),
+        ];
+
+        assert_eq!(expand("{character}: {x}", &vars), "Iop: {character}");
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+
+use anyhow::Result;
+use enigo::{Button, Direction, Key};
+
+use super::{expand, Macro, Step};
+use crate::database::Character;
+
+/// What a hotkey does, planned before anything is sent
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Focuses the window. When `optional`, a failure skips the operations
+    /// up to the next `Focus` or `EndIteration` instead of stopping the plan,
+    /// so that nothing is typed into whatever window kept the focus
+    Focus {
+        window: u32,
+        optional: bool,
+    },
+    /// Ends the steps of a character in `ForEach`
+    EndIteration,
+    Key(Key, Direction),
+    Button(Button, Direction),
+    Text(String),
+    SetClipboard(String),
+    /// Milliseconds
+    Wait(u64),
+    /// Desktop notification
+    Notify(String),
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Focus {
+                window,
+                optional: false,
+            } => write!(f, "focus {window:#x}"),
+            Op::Focus {
+                window,
+                optional: true,
+            } => write!(f, "focus {window:#x} or skip"),
+            Op::EndIteration => write!(f, "end of iteration"),
+            Op::Key(key, direction) => write!(f, "key {key:?} {direction:?}"),
+            Op::Button(button, direction) => write!(f, "button {button:?} {direction:?}"),
+            Op::Text(text) => write!(f, "type {text:?}"),
+            Op::SetClipboard(text) => write!(f, "copy {text:?}"),
+            Op::Wait(ms) => write!(f, "wait {ms}ms"),
+            Op::Notify(message) => write!(f, "notify {message:?}"),
+        }
+    }
+}
+
+/// Focusing a Dofus window takes a click to give the keyboard to the game
+pub fn focus(window: u32, optional: bool) -> Vec<Op> {
+    vec![
+        Op::Focus { window, optional },
+        Op::Button(Button::Middle, Direction::Click),
+    ]
+}
+
+/// Online characters of the active group with their window, in group order
+pub struct Team<'a> {
+    pub windows: &'a [(u32, Character)],
+    /// Window of the leader, `None` while nobody leads
+    pub leader: Option<u32>,
+}
+
+impl<'a> Team<'a> {
+    /// The leader then the other windows, or only the other ones
+    pub fn members(&self, include_leader: bool) -> Vec<&'a (u32, Character)> {
+        let windows = self.windows;
+        let lead = windows.iter().filter(|(id, _)| Some(*id) == self.leader);
+        let others = windows.iter().filter(|(id, _)| Some(*id) != self.leader);
+
+        if include_leader {
+            lead.chain(others).collect()
+        } else {
+            others.collect()
+        }
+    }
+}
+
+/// The operations of the macro, none when its clipboard regex does not match
+/// what `clipboard` reads or when it needs a leader and nobody leads
+pub fn plan(m: &Macro, team: &Team, clipboard: impl FnOnce() -> Result<String>) -> Result<Vec<Op>> {
+    if m.needs_leader() && team.leader.is_none() {
+        return Ok(vec![]);
+    }
+
+    let mut vars = vec![];
+
+    if let Some(regex) = m.clipboard_regex()? {
+        let clipboard = clipboard()?;
+
+        let Some(captures) = regex.captures(&clipboard) else {
+            return Ok(vec![]);
+        };
+
+        for name in regex.capture_names().flatten() {
+            let value = captures.name(name).map(|c| c.as_str()).unwrap_or_default();
+            vars.push((name.to_owned(), value.to_owned()));
+        }
+    }
+
+    let mut ops = vec![];
+    plan_steps(&m.steps, team, &vars, &mut ops);
+
+    Ok(ops)
+}
+
+fn plan_steps(steps: &[Step], team: &Team, vars: &[(String, String)], ops: &mut Vec<Op>) {
+    for step in steps {
+        match step {
+            Step::ForEach {
+                include_leader,
+                focus: focus_each,
+                steps,
+            } => {
+                for (id, character) in team.members(*include_leader) {
+                    if *focus_each {
+                        ops.extend(focus(*id, true));
+                    }
+
+                    let mut vars = vars.to_vec();
+                    vars.push(("character".to_owned(), character.name.to_owned()));
+
+                    plan_steps(steps, team, &vars, ops);
+
+                    if *focus_each {
+                        ops.push(Op::EndIteration);
+                    }
+                }
+            }
+            Step::FocusLeader => {
+                ops.extend(team.leader.into_iter().flat_map(|id| focus(id, false)))
+            }
+            Step::TypeText { text } => ops.push(Op::Text(expand(text, vars))),
+            Step::Copy { text } => ops.push(Op::SetClipboard(expand(text, vars))),
+            // Replaces the content of the chat input
+            Step::Paste => ops.extend([
+                Op::Key(Key::Control, Direction::Press),
+                Op::Wait(40),
+                Op::Key(Key::Unicode('a'), Direction::Click),
+                Op::Wait(40),
+                Op::Key(Key::Unicode('v'), Direction::Click),
+                Op::Wait(40),
+                Op::Key(Key::Control, Direction::Release),
+            ]),
+            Step::Key { key, direction } => ops.push(Op::Key(key.0, (*direction).into())),
+            Step::Click { button, count } => {
+                for _ in 0..*count {
+                    ops.push(Op::Button((*button).into(), Direction::Click));
+                }
+            }
+            Step::Wait { ms } => ops.push(Op::Wait(*ms)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macros::{default_macros, find_macro};
+
+    fn team() -> Vec<(u32, Character)> {
+        ["Iop", "Cra", "Eni"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (i as u32 + 1, Character::new(*name)))
+            .collect()
+    }
+
+    fn plan_macro(name: &str, leader: u32, clipboard: &str) -> Vec<Op> {
+        let windows = team();
+        let team = Team {
+            windows: &windows,
+            leader: Some(leader),
+        };
+        let m = find_macro(&default_macros(), name).unwrap();
+
+        plan(&m, &team, || Ok(clipboard.to_owned())).unwrap()
+    }
+
+    #[test]
+    fn plan_clicks_on_followers() {
+        assert_eq!(
+            plan_macro("DoubleClickFollowers", 2, ""),
+            vec![
+                Op::Focus {
+                    window: 1,
+                    optional: true
+                },
+                Op::Button(Button::Middle, Direction::Click),
+                Op::Button(Button::Left, Direction::Click),
+                Op::Button(Button::Left, Direction::Click),
+                Op::Wait(150),
+                Op::EndIteration,
+                Op::Focus {
+                    window: 3,
+                    optional: true
+                },
+                Op::Button(Button::Middle, Direction::Click),
+                Op::Button(Button::Left, Direction::Click),
+                Op::Button(Button::Left, Direction::Click),
+                Op::Wait(150),
+                Op::EndIteration,
+                Op::Focus {
+                    window: 2,
+                    optional: false
+                },
+                Op::Button(Button::Middle, Direction::Click),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_travel_from_the_clipboard() {
+        let ops = plan_macro("Travel", 3, "Map [-20, 34]");
+
+        assert_eq!(ops[0], Op::SetClipboard("/travel -20,34".to_owned()));
+        // The leader travels first
+        let focused = ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Focus { window, .. } => Some(*window),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(focused, vec![3, 1, 2, 3]);
+        assert_eq!(
+            ops.iter().filter(|op| matches!(op, Op::Wait(_))).count(),
+            3 * 8
+        );
+
+        assert!(plan_macro("Travel", 3, "hello").is_empty());
+    }
+
+    #[test]
+    fn plan_invites_by_name() {
+        let copies = plan_macro("Invite", 1, "")
+            .into_iter()
+            .filter_map(|op| match op {
+                Op::SetClipboard(text) => Some(text),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(copies, vec!["/invite Cra", "/invite Eni"]);
+    }
+
+    #[test]
+    fn display_plans() {
+        let ops = [
+            Op::Focus {
+                window: 0x2a00005,
+                optional: true,
+            },
+            Op::Key(Key::Return, Direction::Click),
+            Op::Wait(40),
+        ];
+
+        assert_eq!(
+            ops.iter().map(Op::to_string).collect::<Vec<_>>(),
+            vec!["focus 0x2a00005 or skip", "key Return Click", "wait 40ms"]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use lazy_regex::regex_is_match;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{OnceLock, RwLock},
+};
+use tauri::{AppHandle, Emitter};
+
+use crate::{
+    database::{get_database, Database},
+    error::CommandError,
+};
+
+/// Profile stored in `data.toml` at the root of the data directory, the other
+/// ones live in `profiles/<name>.toml`
+pub const DEFAULT_PROFILE: &str = "default";
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+static PROFILE: OnceLock<RwLock<String>> = OnceLock::new();
+
+/// Overrides the data directory, only the first call has an effect and it
+/// must happen before the database is loaded
+pub fn set_data_dir(dir: PathBuf) {
+    let _ = DATA_DIR.set(dir);
+}
+
+/// `DOFUS_SWITCHER_DATA_DIR`, then `$XDG_CONFIG_HOME/dofus-switcher`, then
+/// `~/.config/dofus-switcher`
+pub fn get_data_dir() -> &'static Path {
+    DATA_DIR.get_or_init(|| {
+        let dir = std::env::var_os("DOFUS_SWITCHER_DATA_DIR").filter(|dir| !dir.is_empty());
+
+        if let Some(dir) = dir {
+            return dir.into();
+        }
+
+        // Relative paths are invalid according to the XDG spec
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .unwrap_or_else(|| shellexpand::tilde("~/.config").to_string().into());
+
+        config.join("dofus-switcher")
+    })
+}
+
+fn profile() -> &'static RwLock<String> {
+    PROFILE.get_or_init(|| {
+        let name = std::env::var("DOFUS_SWITCHER_PROFILE")
+            .ok()
+            .filter(|name| validate_name(name).is_ok())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_owned());
+
+        RwLock::new(name)
+    })
+}
+
+/// Selects the profile loaded by `get_database`, it must happen before the
+/// database is loaded
+pub fn init_profile(name: String) -> Result<(), CommandError> {
+    validate_name(&name)?;
+
+    *profile().write()? = name;
+
+    Ok(())
+}
+
+pub fn get_profile_name() -> String {
+    profile()
+        .read()
+        .map(|name| name.to_owned())
+        .unwrap_or_else(|_| DEFAULT_PROFILE.to_owned())
+}
+
+/// File of the active profile
+pub fn get_profile_path() -> PathBuf {
+    profile_path(get_data_dir(), &get_profile_name())
+}
+
+fn profile_path(dir: &Path, name: &str) -> PathBuf {
+    if name == DEFAULT_PROFILE {
+        dir.join("data.toml")
+    } else {
+        dir.join("profiles").join(format!("{name}.toml"))
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<(), CommandError> {
+    if !regex_is_match!(r"^[\w-]{1,64}$", name) {
+        return Err(CommandError::Invalid(format!(
+            "invalid profile name \"{name}\", use letters, digits, - and _"
+        )));
+    }
+
+    Ok(())
+}
+
+/// The default profile followed by the saved ones, sorted by name
+fn list_profiles(dir: &Path) -> Vec<String> {
+    let mut profiles = fs::read_dir(dir.join("profiles"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+
+            if path.extension()? != "toml" {
+                return None;
+            }
+
+            let name = path.file_stem()?.to_str()?.to_owned();
+            validate_name(&name).ok().map(|_| name)
+        })
+        .filter(|name| name != DEFAULT_PROFILE)
+        .collect::<Vec<_>>();
+
+    profiles.sort();
+    profiles.insert(0, DEFAULT_PROFILE.to_owned());
+
+    profiles
+}
+
+#[tauri::command]
+pub fn get_profiles() -> Vec<String> {
+    let mut profiles = list_profiles(get_data_dir());
+    let active = get_profile_name();
+
+    // A new profile has no file until something is saved
+    if !profiles.contains(&active) {
+        profiles.push(active);
+    }
+
+    profiles
+}
+
+#[tauri::command]
+pub fn get_profile() -> String {
+    get_profile_name()
+}
+
+/// Loads another profile, it is created by the first change
+#[tauri::command]
+pub fn set_profile(app: AppHandle, name: String) -> Result<String, CommandError> {
+    validate_name(&name)?;
+
+    {
+        let mut db = get_database().write()?;
+        let mut profile = profile().write()?;
+
+        if *profile != name {
+            *db = Database::open(&profile_path(get_data_dir(), &name));
+            profile.clone_from(&name);
+        }
+    }
+
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    let _ = app.emit("profile-changed", &name);
+
+    Ok(name)
+}
+
+/// `profile-2`, `profile-3`... whichever is not taken yet
+fn free_name(profiles: &[String]) -> String {
+    (2..)
+        .map(|n| format!("profile-{n}"))
+        .find(|name| !profiles.contains(name))
+        .expect("there is always a free name")
+}
+
+/// Creates an empty profile under a free name and switches to it, for the
+/// tray which cannot ask for a name
+pub fn create_profile(app: AppHandle) -> Result<String, CommandError> {
+    let name = free_name(&get_profiles());
+
+    set_profile(app, name.clone())?;
+    // Listed from now on even if nothing changes
+    get_database().write()?.save()?;
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_profile_names() {
+        assert!(validate_name("alt-farm_2").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("../main").is_err());
+        assert!(validate_name("team one").is_err());
+    }
+
+    #[test]
+    fn list_saved_profiles() {
+        let dir =
+            std::env::temp_dir().join(format!("dofus-switcher-profiles-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("profiles")).unwrap();
+
+        for file in [
+            "farm.toml",
+            "alt.toml",
+            "alt.toml.1",
+            "default.toml",
+            "bad name.toml",
+        ] {
+            fs::write(dir.join("profiles").join(file), "").unwrap();
+        }
+
+        assert_eq!(list_profiles(&dir), vec!["default", "alt", "farm"]);
+        assert_eq!(free_name(&list_profiles(&dir)), "profile-2");
+        assert_eq!(
+            free_name(&["default".to_owned(), "profile-2".to_owned()]),
+            "profile-3"
+        );
+        assert_eq!(profile_path(&dir, "default"), dir.join("data.toml"));
+        assert_eq!(
+            profile_path(&dir, "alt"),
+            dir.join("profiles").join("alt.toml")
+        );
+    }
+}
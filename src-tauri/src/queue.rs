@@ -0,0 +1,227 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, Thread},
+};
+
+use crate::settings::BusyPolicy;
+
+/// Jobs waiting for a single worker thread, with the cancellation of the
+/// running one
+pub struct JobQueue<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+    cancelled: AtomicBool,
+}
+
+struct State<T> {
+    jobs: VecDeque<T>,
+    busy: bool,
+    /// Unparked on cancellation to cut its waits short
+    worker: Option<Thread>,
+}
+
+impl<T> JobQueue<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                jobs: VecDeque::new(),
+                busy: false,
+                worker: None,
+            }),
+            ready: Condvar::new(),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    // Nothing the queue does can leave the state half updated
+    fn state(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cancel(&self, state: &State<T>) {
+        if !state.busy {
+            return;
+        }
+
+        self.cancelled.store(true, Ordering::SeqCst);
+
+        if let Some(worker) = &state.worker {
+            worker.unpark();
+        }
+    }
+
+    /// Adds the job according to what `policy` does while the worker is busy,
+    /// returns whether it will run
+    pub fn push(&self, job: T, policy: BusyPolicy) -> bool {
+        let mut state = self.state();
+        let busy = state.busy || !state.jobs.is_empty();
+
+        match policy {
+            BusyPolicy::Queue => {}
+            BusyPolicy::Drop if busy => return false,
+            BusyPolicy::Drop => {}
+            BusyPolicy::Preempt => {
+                state.jobs.clear();
+                self.cancel(&state);
+            }
+        }
+
+        state.jobs.push_back(job);
+        self.ready.notify_one();
+
+        true
+    }
+
+    /// Forgets the waiting jobs and cancels the running one
+    pub fn abort(&self) {
+        let mut state = self.state();
+
+        state.jobs.clear();
+        self.cancel(&state);
+    }
+
+    /// Waits for the next job, the worker calls `done` once it has run it
+    pub fn next(&self) -> T {
+        let mut state = self.state();
+
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                state.busy = true;
+                state.worker = Some(thread::current());
+                self.cancelled.store(false, Ordering::SeqCst);
+
+                return job;
+            }
+
+            state = self
+                .ready
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    pub fn done(&self) {
+        self.state().busy = false;
+    }
+
+    /// Set when the running job should stop at its next step, the worker is
+    /// unparked at the same time
+    pub fn cancelled(&self) -> &AtomicBool {
+        &self.cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    fn is_cancelled(queue: &JobQueue<u32>) -> bool {
+        queue.cancelled().load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn queue_jobs_in_order() {
+        let queue = Arc::new(JobQueue::new());
+
+        let worker = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let jobs = [queue.next(), queue.next()];
+                queue.done();
+                jobs
+            })
+        };
+
+        assert!(queue.push(1, BusyPolicy::Queue));
+        assert!(queue.push(2, BusyPolicy::Queue));
+
+        assert_eq!(worker.join().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn drop_jobs_while_busy() {
+        let queue = JobQueue::new();
+
+        assert!(queue.push(1, BusyPolicy::Drop));
+        assert!(!queue.push(2, BusyPolicy::Drop));
+        assert_eq!(queue.next(), 1);
+        assert!(!queue.push(3, BusyPolicy::Drop));
+
+        queue.done();
+        assert!(queue.push(4, BusyPolicy::Drop));
+        assert_eq!(queue.next(), 4);
+    }
+
+    #[test]
+    fn preempt_the_running_job() {
+        let queue = JobQueue::new();
+
+        queue.push(1, BusyPolicy::Queue);
+        assert_eq!(queue.next(), 1);
+        queue.push(2, BusyPolicy::Queue);
+        assert!(!is_cancelled(&queue));
+
+        assert!(queue.push(3, BusyPolicy::Preempt));
+        assert!(is_cancelled(&queue));
+
+        queue.done();
+        assert_eq!(queue.next(), 3);
+        assert!(!is_cancelled(&queue));
+    }
+
+    #[test]
+    fn abort_clears_the_queue() {
+        let queue = JobQueue::new();
+
+        // Nothing runs, nothing to cancel
+        queue.abort();
+        assert!(!is_cancelled(&queue));
+
+        queue.push(1, BusyPolicy::Queue);
+        assert_eq!(queue.next(), 1);
+        queue.push(2, BusyPolicy::Queue);
+
+        queue.abort();
+        assert!(is_cancelled(&queue));
+
+        queue.done();
+        queue.push(3, BusyPolicy::Queue);
+        assert_eq!(queue.next(), 3);
+    }
+
+    #[test]
+    fn abort_wakes_the_worker() {
+        let queue = Arc::new(JobQueue::new());
+        queue.push(1, BusyPolicy::Queue);
+
+        let worker = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                queue.next();
+                let start = Instant::now();
+
+                while !is_cancelled(&queue) {
+                    thread::park_timeout(Duration::from_secs(10));
+                }
+
+                start.elapsed()
+            })
+        };
+
+        while !queue.state().busy {
+            thread::yield_now();
+        }
+        queue.abort();
+
+        assert!(worker.join().unwrap() < Duration::from_secs(10));
+    }
+}
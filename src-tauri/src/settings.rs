@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+    database::get_database,
+    desktop::{self, FocusStrategy, Matcher},
+    error::CommandError,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Settings {
+    /// Milliseconds to wait for the window manager to focus a window
+    pub focus_timeout: u64,
+    pub focus_strategy: FocusStrategy,
+    /// Act on the group with the most characters online instead of the
+    /// selected one
+    pub auto_group: bool,
+    /// Rules telling which windows are Dofus clients and who they show, tried
+    /// before the default ones, the first matching one is used
+    pub matchers: Vec<Matcher>,
+    pub slot_mode: SlotMode,
+    /// Shows a desktop notification when a `Focus` hotkey targets an offline
+    /// character, only with `SlotMode::Fixed`
+    pub notify_offline: bool,
+    pub leader_fallback: LeaderFallback,
+    pub busy_policy: BusyPolicy,
+}
+
+/// What a hotkey pressed while another one still runs does, e.g. during a
+/// travel
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BusyPolicy {
+    /// Runs once the previous ones are done
+    #[default]
+    Queue,
+    /// Nothing
+    Drop,
+    /// Cancels the running one and the waiting ones, then runs
+    Preempt,
+}
+
+/// Who leads travels, invites and clicks while the leader of the group is
+/// offline or disabled
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LeaderFallback {
+    /// The first online member in group order
+    #[default]
+    FirstOnline,
+    /// Nobody, these hotkeys do nothing until the leader is back
+    Stop,
+}
+
+/// How `Focus` hotkeys pick their character
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SlotMode {
+    /// The member holding the slot, nothing happens while it is offline
+    #[default]
+    Fixed,
+    /// The n-th online member, the following ones move up when a character
+    /// disconnects
+    Compact,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            focus_timeout: 1000,
+            focus_strategy: Default::default(),
+            auto_group: false,
+            matchers: vec![],
+            slot_mode: Default::default(),
+            notify_offline: false,
+            leader_fallback: Default::default(),
+            busy_policy: Default::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn focus_timeout(&self) -> Duration {
+        Duration::from_millis(self.focus_timeout)
+    }
+}
+
+#[tauri::command]
+pub fn get_settings() -> Result<Settings, CommandError> {
+    let db = get_database().read()?;
+
+    Ok(db.settings.clone())
+}
+
+#[tauri::command]
+pub fn set_settings(settings: Settings) -> Result<Settings, CommandError> {
+    let settings = {
+        let mut db = get_database().write()?;
+
+        db.update(|db| {
+            desktop::validate_matchers(&settings.matchers)?;
+            db.settings = settings;
+            Ok(())
+        })?;
+
+        desktop::set_matchers(&db.settings.matchers)?;
+
+        db.settings.clone()
+    };
+
+    #[cfg(desktop)]
+    crate::tray::refresh();
+
+    Ok(settings)
+}
@@ -1,289 +1,627 @@
 use std::{
+    collections::HashSet,
     sync::{OnceLock, RwLock},
-    thread::{self, sleep},
-    time::Duration,
+    thread,
 };
 
 use anyhow::Result;
-use arboard::Clipboard;
-use enigo::{Enigo, Keyboard, Mouse};
-use lazy_regex::regex_captures;
 use rdev::{listen, EventType};
 
-use crate::desktop::{get_character_windows, Desktop};
+use crate::{
+    database::{get_database, report, Character},
+    desktop::{self, get_active_members, Slot},
+    error::CommandError,
+    group::{cycle_active_group, promote_leader},
+    input,
+    keymap::{find_action, Action},
+    macros::{find_macro, focus, plan, DryRun, Executor, LiveExecutor, Macro, Op, Team},
+    queue::JobQueue,
+    settings::{LeaderFallback, Settings, SlotMode},
+};
+use uuid::Uuid;
 
-static BACKSLASH_PRESSED: OnceLock<RwLock<bool>> = OnceLock::new();
+static PRESSED_KEYS: OnceLock<RwLock<HashSet<rdev::Key>>> = OnceLock::new();
 
-fn is_backslash_pressed() -> &'static RwLock<bool> {
-    BACKSLASH_PRESSED.get_or_init(|| false.into())
+fn get_pressed_keys() -> &'static RwLock<HashSet<rdev::Key>> {
+    PRESSED_KEYS.get_or_init(Default::default)
 }
 
-pub fn watch() {
-    thread::spawn(|| {
-        let _ = listen(|event| {
-            let Ok(desktop) = Desktop::connect() else {
-                return;
-            };
-
-            let Ok(Some(active_window)) = desktop.get_active_window() else {
-                return;
-            };
-
-            let Ok(wins) = get_character_windows().read() else {
-                return;
-            };
-
-            let Some(wins) = wins.to_owned() else {
-                return;
-            };
-
-            let wins = wins.iter().filter(|(_, c)| c.enabled).collect::<Vec<_>>();
-
-            let Ok(mut enigo) = Enigo::new(&enigo::Settings::default()) else {
-                return;
-            };
-
-            if let EventType::KeyPress(rdev::Key::IntlBackslash) = event.event_type {
-                let Ok(mut pressed) = is_backslash_pressed().write() else {
-                    return;
-                };
-
-                *pressed = true;
-            }
-
-            if let EventType::KeyRelease(rdev::Key::IntlBackslash) = event.event_type {
-                let Ok(mut pressed) = is_backslash_pressed().write() else {
-                    return;
-                };
-
-                *pressed = false;
-            }
-
-            // Go to window 1
-            if let (EventType::KeyPress(rdev::Key::F1), Some((id, _))) =
-                (event.event_type, wins.get(0))
-            {
-                let _ = select_window(&desktop, &mut enigo, id.to_owned());
-            }
-
-            // Go to window 2
-            if let (EventType::KeyPress(rdev::Key::F2), Some((id, _))) =
-                (event.event_type, wins.get(1))
-            {
-                let _ = select_window(&desktop, &mut enigo, id.to_owned());
-            }
-
-            // Go to window 3
-            if let (EventType::KeyPress(rdev::Key::F3), Some((id, _))) =
-                (event.event_type, wins.get(2))
-            {
-                let _ = select_window(&desktop, &mut enigo, id.to_owned());
-            }
-
-            // Go to window 4
-            if let (EventType::KeyPress(rdev::Key::F4), Some((id, _))) =
-                (event.event_type, wins.get(3))
-            {
-                let _ = select_window(&desktop, &mut enigo, id.to_owned());
-            }
-
-            // Go to window 5
-            if let (EventType::KeyPress(rdev::Key::F5), Some((id, _))) =
-                (event.event_type, wins.get(4))
-            {
-                let _ = select_window(&desktop, &mut enigo, id.to_owned());
-            }
-
-            // Go to window 6
-            if let (EventType::KeyPress(rdev::Key::F6), Some((id, _))) =
-                (event.event_type, wins.get(5))
-            {
-                let _ = select_window(&desktop, &mut enigo, id.to_owned());
-            }
-
-            // Go to window 7
-            if let (EventType::KeyPress(rdev::Key::F7), Some((id, _))) =
-                (event.event_type, wins.get(6))
-            {
-                let _ = select_window(&desktop, &mut enigo, id.to_owned());
-            }
-
-            // Go to window 8
-            if let (EventType::KeyPress(rdev::Key::F8), Some((id, _))) =
-                (event.event_type, wins.get(7))
-            {
-                let _ = select_window(&desktop, &mut enigo, id.to_owned());
-            }
+/// A hotkey pressed, with the configuration at that time
+struct Job {
+    action: Action,
+    settings: Settings,
+    macros: Vec<Macro>,
+}
 
-            // Travel
-            if let EventType::KeyPress(rdev::Key::PageUp) = event.event_type {
-                let Ok(pressed) = is_backslash_pressed().read() else {
-                    return;
-                };
+// Hotkeys run one at a time on the worker so that the listener keeps reading
+// keys, and the abort hotkey with it, during long macros
+static JOBS: JobQueue<Job> = JobQueue::new();
 
-                let skip = if pressed.to_owned() { 1 } else { 0 };
+pub fn watch() {
+    thread::spawn(|| loop {
+        let job = JOBS.next();
+        run_job(job);
+        JOBS.done();
+    });
 
-                let Ok(mut clipboard) = Clipboard::new() else {
-                    return;
-                };
+    thread::spawn(|| {
+        let _ = listen(|event| {
+            let key = match event.event_type {
+                EventType::KeyPress(key) => key,
+                EventType::KeyRelease(key) => {
+                    if let Ok(mut pressed) = get_pressed_keys().write() {
+                        pressed.remove(&key);
+                    }
 
-                let Ok(selection) = clipboard.get_text() else {
                     return;
-                };
-
-                if !selection.starts_with("/travel ") {
-                    let Some((_, x, y)) = regex_captures!(r#"(-?\d+),(-?\d+)"#, &selection) else {
-                        return;
-                    };
-                    if clipboard.set_text(&format!("/travel {x},{y}")).is_err() {
-                        return;
-                    };
-                };
-
-                for (id, _) in wins.iter().skip(skip) {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Space, enigo::Direction::Click);
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Control, enigo::Direction::Press);
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Unicode('a'), enigo::Direction::Click);
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Unicode('v'), enigo::Direction::Click);
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Control, enigo::Direction::Release);
-
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Return, enigo::Direction::Click);
-
-                    sleep(Duration::from_millis(200));
-
-                    let _ = enigo.key(enigo::Key::Return, enigo::Direction::Click);
-
-                    sleep(Duration::from_millis(100));
-                }
-
-                if let Some((id, _)) = wins.first() {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
                 }
-            }
+                _ => return,
+            };
 
-            // Send group invit
-            if let EventType::KeyPress(rdev::Key::Home) = event.event_type {
-                if let Some((id, _)) = wins.first() {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                } else {
-                    return;
-                }
-                let Ok(mut clipboard) = Clipboard::new() else {
+            let job = {
+                let Ok(mut pressed) = get_pressed_keys().write() else {
                     return;
                 };
 
-                sleep(Duration::from_millis(40));
-                let _ = enigo.key(enigo::Key::Space, enigo::Direction::Click);
-
-                for (_, character) in wins.iter().skip(1) {
-                    let _ = clipboard.set_text(&format!("/invite {}", character.name));
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Control, enigo::Direction::Press);
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Unicode('a'), enigo::Direction::Click);
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Unicode('v'), enigo::Direction::Click);
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Control, enigo::Direction::Release);
-                    sleep(Duration::from_millis(40));
-                    let _ = enigo.key(enigo::Key::Return, enigo::Direction::Click);
-                    sleep(Duration::from_millis(100));
-                }
-            }
+                pressed.insert(key);
 
-            // Go right click to all with or without leader
-            if let EventType::KeyPress(rdev::Key::End) = event.event_type {
-                let Ok(pressed) = is_backslash_pressed().read() else {
+                let Ok(db) = get_database().read() else {
                     return;
                 };
 
-                let skip = if pressed.to_owned() { 1 } else { 0 };
-
-                for (id, _) in wins.iter().skip(skip) {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                    let _ = enigo.button(enigo::Button::Right, enigo::Direction::Click);
-                    sleep(Duration::from_millis(150));
-                }
-
-                if let Some((id, _)) = wins.first() {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                }
-            }
-
-            // Go left click to all with or without leader
-            if let EventType::KeyPress(rdev::Key::Delete) = event.event_type {
-                let Ok(pressed) = is_backslash_pressed().read() else {
+                let Some(action) = find_action(&db.keymap, key, &pressed) else {
                     return;
                 };
 
-                let skip = if pressed.to_owned() { 1 } else { 0 };
-
-                for (id, _) in wins.iter().skip(skip) {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                    let _ = enigo.button(enigo::Button::Left, enigo::Direction::Click);
-                    sleep(Duration::from_millis(150));
+                Job {
+                    action: action.clone(),
+                    settings: db.settings.clone(),
+                    macros: db.macros.clone(),
                 }
+            };
 
-                if let Some((id, _)) = wins.first() {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                }
+            if job.action == Action::Abort {
+                JOBS.abort();
+            } else {
+                let policy = job.settings.busy_policy;
+                JOBS.push(job, policy);
             }
+        });
+    });
+}
 
-            // Go left double click to all with or without leader
-            if let EventType::KeyPress(rdev::Key::PageDown) = event.event_type {
-                let Ok(pressed) = is_backslash_pressed().read() else {
-                    return;
-                };
-
-                let skip = if pressed.to_owned() { 1 } else { 0 };
+fn run_job(job: Job) {
+    let Job {
+        action,
+        settings,
+        macros,
+    } = job;
+
+    let Ok(desktop) = desktop::connect() else {
+        return;
+    };
+
+    let Ok(Some(active_window)) = desktop.get_active_window() else {
+        return;
+    };
+
+    let Some(members) = get_active_members() else {
+        return;
+    };
+
+    let Ok(mut input) = input::connect() else {
+        return;
+    };
+
+    let mut live;
+    let mut dry_run;
+    let executor: &mut dyn Executor = match DryRun::from_env() {
+        Some(executor) => {
+            dry_run = DryRun {
+                clipboard: input.get_clipboard().unwrap_or_default(),
+                ..executor
+            };
+            &mut dry_run
+        }
+        None => {
+            live = LiveExecutor {
+                desktop: desktop.as_ref(),
+                input: input.as_mut(),
+                settings: &settings,
+                cancelled: JOBS.cancelled(),
+            };
+            &mut live
+        }
+    };
+
+    let mut shortcut = Shortcut {
+        executor,
+        wins: &members.windows,
+        slots: &members.slots,
+        leader: members.leader,
+        active_window,
+        settings: settings.clone(),
+        macros,
+    };
+
+    if let Err(e) = shortcut.run(&action) {
+        match e.downcast::<CommandError>() {
+            Ok(e) => report(e),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
 
-                for (id, _) in wins.iter().skip(skip) {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                    let _ = enigo.button(enigo::Button::Left, enigo::Direction::Click);
-                    let _ = enigo.button(enigo::Button::Left, enigo::Direction::Click);
-                    sleep(Duration::from_millis(150));
-                }
+struct Shortcut<'a> {
+    executor: &'a mut dyn Executor,
+    wins: &'a [(u32, Character)],
+    slots: &'a [Slot],
+    leader: Option<Uuid>,
+    active_window: u32,
+    settings: Settings,
+    macros: Vec<Macro>,
+}
 
-                if let Some((id, _)) = wins.first() {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                }
+impl Shortcut<'_> {
+    fn run(&mut self, action: &Action) -> Result<()> {
+        match action {
+            Action::NextGroup => Ok(cycle_active_group(false)?),
+            Action::PreviousGroup => Ok(cycle_active_group(true)?),
+            Action::PromoteLeader => Ok(promote_leader(self.active_window)?),
+            action => {
+                let plan = self.plan(action)?;
+                self.executor.execute(&plan)
             }
+        }
+    }
+
+    /// What the action does in the windows of the active group
+    fn plan(&mut self, action: &Action) -> Result<Vec<Op>> {
+        match action {
+            Action::Focus(slot) => Ok(self.focus(*slot)),
+            Action::Next => Ok(self.cycle(false)),
+            Action::Previous => Ok(self.cycle(true)),
+            action => match action.macro_name() {
+                Some(name) => self.play(name),
+                None => Ok(vec![]),
+            },
+        }
+    }
+
+    /// Window of the leader, or of its fallback while it is offline
+    fn leader_window(&self) -> Option<u32> {
+        let leader = self
+            .leader
+            .and_then(|leader| self.wins.iter().find(|(_, c)| c.id == leader));
+
+        match leader {
+            Some((id, _)) => Some(*id),
+            None if self.settings.leader_fallback == LeaderFallback::FirstOnline => {
+                self.wins.first().map(|(id, _)| *id)
+            }
+            None => None,
+        }
+    }
+
+    // Go to the character in slot n, or to the n-th window in compact mode
+    fn focus(&self, slot: usize) -> Vec<Op> {
+        if self.settings.slot_mode == SlotMode::Compact {
+            let Some((id, _)) = slot.checked_sub(1).and_then(|i| self.wins.get(i)) else {
+                return vec![];
+            };
 
-            // Go to previous or next
-            if let EventType::KeyPress(rdev::Key::Tab) = event.event_type {
-                let Some(active_pos) = wins.iter().position(|(win, _)| win == &active_window)
-                else {
-                    return;
-                };
-
-                let Ok(pressed) = is_backslash_pressed().read() else {
-                    return;
-                };
+            return focus(*id, false);
+        }
 
-                let next_pos = if pressed.to_owned() {
-                    (active_pos + wins.len() - 1) % wins.len()
-                } else {
-                    (active_pos + 1) % wins.len()
-                };
+        let Some(target) = self.slots.iter().find(|s| s.slot == slot) else {
+            return vec![];
+        };
 
-                if let Some((id, _)) = wins.get(next_pos) {
-                    let _ = select_window(&desktop, &mut enigo, id.to_owned());
-                }
+        match target.window {
+            Some(id) => focus(id, false),
+            None if self.settings.notify_offline => {
+                vec![Op::Notify(format!("{} is offline", target.character.key()))]
             }
-        });
-    });
+            None => vec![],
+        }
+    }
+
+    // Go to previous or next
+    fn cycle(&self, reverse: bool) -> Vec<Op> {
+        let Some(active_pos) = self
+            .wins
+            .iter()
+            .position(|(win, _)| win == &self.active_window)
+        else {
+            return vec![];
+        };
+
+        let next_pos = if reverse {
+            (active_pos + self.wins.len() - 1) % self.wins.len()
+        } else {
+            (active_pos + 1) % self.wins.len()
+        };
+
+        match self.wins.get(next_pos) {
+            Some((id, _)) => focus(*id, false),
+            None => vec![],
+        }
+    }
+
+    /// Plans the macro, the ones needing a leader do nothing while nobody
+    /// leads
+    fn play(&mut self, name: &str) -> Result<Vec<Op>> {
+        let Some(m) = find_macro(&self.macros, name) else {
+            return Ok(vec![]);
+        };
+
+        let team = Team {
+            windows: self.wins,
+            leader: self.leader_window(),
+        };
+
+        plan(&m, &team, || self.executor.get_clipboard())
+    }
 }
 
-fn select_window(desktop: &Desktop, enigo: &mut Enigo, id: u32) -> Result<()> {
-    desktop.show_window(id)?;
-    enigo.button(enigo::Button::Middle, enigo::Direction::Click)?;
-
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use enigo::{Button, Direction, Key};
+
+    use super::*;
+    use crate::{
+        desktop::{ActiveMembers, Desktop, FakeDesktop},
+        input::{FakeInput, InputEvent},
+        macros::{default_macros, Step},
+    };
+
+    fn setup(names: &[&str]) -> (FakeDesktop, Vec<(u32, Character)>) {
+        let desktop = FakeDesktop::new();
+        let wins = names
+            .iter()
+            .map(|name| (desktop.open_window(*name), Character::new(*name)))
+            .collect::<Vec<_>>();
+
+        (desktop, wins)
+    }
+
+    /// Everyone is online, in slot order, the first one leads
+    fn members(wins: &[(u32, Character)]) -> ActiveMembers {
+        ActiveMembers {
+            windows: wins.to_vec(),
+            slots: wins
+                .iter()
+                .enumerate()
+                .map(|(i, (id, character))| Slot {
+                    slot: i + 1,
+                    character: character.clone(),
+                    window: Some(*id),
+                })
+                .collect(),
+            leader: wins.first().map(|(_, c)| c.id),
+        }
+    }
+
+    fn run(
+        desktop: &FakeDesktop,
+        input: &mut FakeInput,
+        wins: &[(u32, Character)],
+        action: Action,
+    ) {
+        run_with(desktop, input, &members(wins), Default::default(), action);
+    }
+
+    fn run_with(
+        desktop: &FakeDesktop,
+        input: &mut FakeInput,
+        members: &ActiveMembers,
+        settings: Settings,
+        action: Action,
+    ) {
+        let active_window = desktop.get_active_window().unwrap().unwrap_or_default();
+        let mut executor = LiveExecutor {
+            desktop,
+            input,
+            settings: &settings,
+            cancelled: &AtomicBool::new(false),
+        };
+        let mut shortcut = Shortcut {
+            executor: &mut executor,
+            wins: &members.windows,
+            slots: &members.slots,
+            leader: members.leader,
+            active_window,
+            settings: settings.clone(),
+            macros: default_macros(),
+        };
+
+        shortcut.run(&action).unwrap();
+    }
+
+    #[test]
+    fn next_focuses_the_following_window() {
+        let (desktop, wins) = setup(&["Iop", "Cra", "Eni"]);
+        let mut input = FakeInput::new();
+
+        desktop.focus_window(Some(wins[1].0));
+        run(&desktop, &mut input, &wins, Action::Next);
+
+        assert_eq!(desktop.shown_windows(), vec![wins[2].0]);
+        assert_eq!(desktop.get_active_window().unwrap(), Some(wins[2].0));
+
+        run(&desktop, &mut input, &wins, Action::Next);
+        run(&desktop, &mut input, &wins, Action::Previous);
+
+        assert_eq!(
+            desktop.shown_windows(),
+            vec![wins[2].0, wins[0].0, wins[2].0]
+        );
+    }
+
+    #[test]
+    fn focus_ignores_missing_slots() {
+        let (desktop, wins) = setup(&["Iop", "Cra"]);
+        let mut input = FakeInput::new();
+
+        desktop.focus_window(Some(wins[0].0));
+        run(&desktop, &mut input, &wins, Action::Focus(2));
+        run(&desktop, &mut input, &wins, Action::Focus(3));
+        run(&desktop, &mut input, &wins, Action::Focus(0));
+
+        assert_eq!(desktop.shown_windows(), vec![wins[1].0]);
+        assert_eq!(
+            input.events,
+            vec![InputEvent::Button(Button::Middle, Direction::Click)]
+        );
+    }
+
+    #[test]
+    fn focus_keeps_slots_of_offline_characters() {
+        let (desktop, wins) = setup(&["Iop", "Eni"]);
+        let mut input = FakeInput::new();
+
+        // Cra in slot 2 is offline, Eni moves to slot 3
+        let mut members = members(&wins);
+        members.slots[1].slot = 3;
+        members.slots.insert(
+            1,
+            Slot {
+                slot: 2,
+                character: Character::new("Cra"),
+                window: None,
+            },
+        );
+        let compact = Settings {
+            slot_mode: SlotMode::Compact,
+            ..Default::default()
+        };
+
+        run_with(
+            &desktop,
+            &mut input,
+            &members,
+            Default::default(),
+            Action::Focus(2),
+        );
+        assert!(desktop.shown_windows().is_empty());
+
+        run_with(
+            &desktop,
+            &mut input,
+            &members,
+            Default::default(),
+            Action::Focus(3),
+        );
+        run_with(&desktop, &mut input, &members, compact, Action::Focus(2));
+        assert_eq!(desktop.shown_windows(), vec![wins[1].0, wins[1].0]);
+    }
+
+    #[test]
+    fn travel_followers_pastes_command_in_each_follower() {
+        let (desktop, wins) = setup(&["Iop", "Cra", "Eni"]);
+        let mut input = FakeInput::new();
+        input.clipboard = "[-20,34]".to_owned();
+
+        desktop.focus_window(Some(wins[0].0));
+        run(&desktop, &mut input, &wins, Action::TravelFollowers);
+
+        assert_eq!(
+            desktop.shown_windows(),
+            vec![wins[1].0, wins[2].0, wins[0].0]
+        );
+
+        let follower = vec![
+            InputEvent::Button(Button::Middle, Direction::Click),
+            InputEvent::Key(Key::Space, Direction::Click),
+            InputEvent::Key(Key::Control, Direction::Press),
+            InputEvent::Key(Key::Unicode('a'), Direction::Click),
+            InputEvent::Key(Key::Unicode('v'), Direction::Click),
+            InputEvent::Key(Key::Control, Direction::Release),
+            InputEvent::Key(Key::Return, Direction::Click),
+            InputEvent::Key(Key::Return, Direction::Click),
+        ];
+
+        let mut expected = vec![InputEvent::SetClipboard("/travel -20,34".to_owned())];
+        expected.extend(follower.clone());
+        expected.extend(follower);
+        expected.push(InputEvent::Button(Button::Middle, Direction::Click));
+
+        assert_eq!(input.events, expected);
+    }
+
+    #[test]
+    fn followers_exclude_the_designated_leader() {
+        let (desktop, wins) = setup(&["Iop", "Cra", "Eni"]);
+        let mut input = FakeInput::new();
+        let mut members = members(&wins);
+
+        members.leader = Some(wins[1].1.id);
+        run_with(
+            &desktop,
+            &mut input,
+            &members,
+            Default::default(),
+            Action::LeftClickFollowers,
+        );
+        assert_eq!(
+            desktop.shown_windows(),
+            vec![wins[0].0, wins[2].0, wins[1].0]
+        );
+
+        // Offline leaders fall back to the first online character
+        members.leader = Some(Uuid::new_v4());
+        input.events.clear();
+        run_with(
+            &desktop,
+            &mut input,
+            &members,
+            Default::default(),
+            Action::Invite,
+        );
+        assert_eq!(desktop.shown_windows()[3], wins[0].0);
+        assert_eq!(
+            input
+                .events
+                .iter()
+                .filter_map(|e| match e {
+                    InputEvent::SetClipboard(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            vec!["/invite Cra", "/invite Eni"]
+        );
+
+        let stop = Settings {
+            leader_fallback: LeaderFallback::Stop,
+            ..Default::default()
+        };
+        input.events.clear();
+        run_with(&desktop, &mut input, &members, stop, Action::Travel);
+        assert!(input.events.is_empty());
+        assert_eq!(desktop.shown_windows().len(), 4);
+    }
+
+    #[test]
+    fn click_skips_windows_that_cannot_be_focused() {
+        let (desktop, wins) = setup(&["Iop", "Cra", "Eni"]);
+        let mut input = FakeInput::new();
+
+        desktop.refuse_focus(wins[1].0);
+        desktop.close_window(wins[2].0);
+        run(&desktop, &mut input, &wins, Action::RightClick);
+
+        assert_eq!(desktop.shown_windows(), vec![wins[0].0, wins[0].0]);
+        assert_eq!(
+            input.events,
+            vec![
+                InputEvent::Button(Button::Middle, Direction::Click),
+                InputEvent::Button(Button::Right, Direction::Click),
+                InputEvent::Button(Button::Middle, Direction::Click),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_configured_macros() {
+        let (_, wins) = setup(&["Iop", "Cra"]);
+        let members = members(&wins);
+        let mut executor = DryRun::default();
+
+        let mut shortcut = Shortcut {
+            executor: &mut executor,
+            wins: &members.windows,
+            slots: &members.slots,
+            leader: Some(wins[1].1.id),
+            active_window: wins[0].0,
+            settings: Default::default(),
+            macros: vec![
+                Macro {
+                    name: "Hello".to_owned(),
+                    clipboard: None,
+                    steps: vec![Step::ForEach {
+                        include_leader: true,
+                        focus: false,
+                        steps: vec![Step::TypeText {
+                            text: "hi {character}".to_owned(),
+                        }],
+                    }],
+                },
+                Macro {
+                    name: "Bye".to_owned(),
+                    clipboard: None,
+                    steps: vec![Step::TypeText {
+                        text: "bye".to_owned(),
+                    }],
+                },
+            ],
+        };
+
+        assert_eq!(
+            shortcut.plan(&Action::Macro("Hello".to_owned())).unwrap(),
+            vec![Op::Text("hi Cra".to_owned()), Op::Text("hi Iop".to_owned()),]
+        );
+        assert!(shortcut
+            .plan(&Action::Macro("Missing".to_owned()))
+            .unwrap()
+            .is_empty());
+
+        shortcut.run(&Action::Macro("Hello".to_owned())).unwrap();
+
+        // Only the macros going through the characters wait for a leader
+        shortcut.leader = Some(Uuid::new_v4());
+        shortcut.settings.leader_fallback = LeaderFallback::Stop;
+        assert!(shortcut
+            .plan(&Action::Macro("Hello".to_owned()))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            shortcut.plan(&Action::Macro("Bye".to_owned())).unwrap(),
+            vec![Op::Text("bye".to_owned())]
+        );
+
+        assert_eq!(executor.log, vec!["type \"hi Cra\"", "type \"hi Iop\""]);
+    }
+
+    #[test]
+    fn plan_offline_notifications() {
+        let (_, wins) = setup(&["Iop"]);
+        let mut members = members(&wins);
+        members.slots[0].window = None;
+        let mut executor = DryRun::default();
+
+        let mut shortcut = Shortcut {
+            executor: &mut executor,
+            wins: &members.windows,
+            slots: &members.slots,
+            leader: members.leader,
+            active_window: wins[0].0,
+            settings: Settings {
+                notify_offline: true,
+                ..Default::default()
+            },
+            macros: vec![],
+        };
+
+        assert_eq!(
+            shortcut.plan(&Action::Focus(1)).unwrap(),
+            vec![Op::Notify("Iop is offline".to_owned())]
+        );
+
+        shortcut.settings.notify_offline = false;
+        assert!(shortcut.plan(&Action::Focus(1)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn travel_without_coordinates_does_nothing() {
+        let (desktop, wins) = setup(&["Iop", "Cra"]);
+        let mut input = FakeInput::new();
+        input.clipboard = "hello".to_owned();
+
+        run(&desktop, &mut input, &wins, Action::Travel);
+
+        assert!(desktop.shown_windows().is_empty());
+        assert!(input.events.is_empty());
+    }
 }
@@ -1,13 +1,107 @@
+use std::sync::OnceLock;
+
 use tauri::{
-    menu::{Menu, MenuItem},
+    menu::{CheckMenuItem, IsMenuItem, Menu, MenuItem, PredefinedMenuItem, Submenu},
     tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
-    Manager, Runtime,
+    AppHandle, Manager,
+};
+
+use crate::{
+    database::{get_database, report},
+    group::{set_active_group, set_auto_group},
+    profile::{create_profile, get_profile_name, get_profiles, set_profile},
 };
 
-pub fn create_tray<R: Runtime>(app: &tauri::AppHandle<R>) -> tauri::Result<()> {
+static APP: OnceLock<AppHandle> = OnceLock::new();
+
+fn create_menu(app: &AppHandle) -> tauri::Result<Menu<tauri::Wry>> {
+    let (groups, active_group, auto_group) = match get_database().read() {
+        Ok(db) => (
+            db.groups
+                .iter()
+                .map(|g| (g.id, g.name.to_owned()))
+                .collect(),
+            db.active_group().map(|g| g.id),
+            db.settings.auto_group,
+        ),
+        Err(_) => (vec![], None, false),
+    };
+
+    let auto_i = CheckMenuItem::with_id(app, "auto_group", "Auto", true, auto_group, None::<&str>)?;
+    let separator = PredefinedMenuItem::separator(app)?;
+    let group_items = groups
+        .into_iter()
+        .map(|(id, name)| {
+            let checked = !auto_group && active_group == Some(id);
+            CheckMenuItem::with_id(
+                app,
+                format!("group:{id}"),
+                &name,
+                true,
+                checked,
+                None::<&str>,
+            )
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+
+    let mut items: Vec<&dyn IsMenuItem<tauri::Wry>> = vec![&auto_i, &separator];
+    items.extend(group_items.iter().map(|i| i as &dyn IsMenuItem<tauri::Wry>));
+
+    let group_menu = Submenu::with_items(app, "Group", true, &items)?;
+
+    let profile = get_profile_name();
+    let profile_items = get_profiles()
+        .into_iter()
+        .map(|name| {
+            CheckMenuItem::with_id(
+                app,
+                format!("profile:{name}"),
+                &name,
+                true,
+                name == profile,
+                None::<&str>,
+            )
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+
+    let profile_separator = PredefinedMenuItem::separator(app)?;
+    let new_profile_i = MenuItem::with_id(app, "new_profile", "New", true, None::<&str>)?;
+
+    let mut items = profile_items
+        .iter()
+        .map(|i| i as &dyn IsMenuItem<tauri::Wry>)
+        .collect::<Vec<_>>();
+    items.extend([
+        &profile_separator as &dyn IsMenuItem<tauri::Wry>,
+        &new_profile_i,
+    ]);
+
+    let profile_menu = Submenu::with_items(app, "Profile", true, &items)?;
     let quit_i = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
     let settings_i = MenuItem::with_id(app, "settings", "Settings", true, None::<&str>)?;
-    let menu = Menu::with_items(app, &[&settings_i, &quit_i])?;
+
+    Menu::with_items(app, &[&group_menu, &profile_menu, &settings_i, &quit_i])
+}
+
+/// Rebuilds the tray menu after groups, the active group or the profile
+/// changed
+pub fn refresh() {
+    let Some(app) = APP.get() else {
+        return;
+    };
+
+    let Some(tray) = app.tray_by_id("tray") else {
+        return;
+    };
+
+    if let Ok(menu) = create_menu(app) {
+        let _ = tray.set_menu(Some(menu));
+    }
+}
+
+pub fn create_tray(app: &AppHandle) -> tauri::Result<()> {
+    let _ = APP.set(app.clone());
+    let menu = create_menu(app)?;
 
     let _ = TrayIconBuilder::with_id("tray")
         .icon(app.default_window_icon().unwrap().clone())
@@ -15,6 +109,35 @@ pub fn create_tray<R: Runtime>(app: &tauri::AppHandle<R>) -> tauri::Result<()> {
         .menu_on_left_click(false)
         .on_menu_event(move |app, event| match event.id.as_ref() {
             "quit" => app.exit(0),
+            "auto_group" => {
+                let auto_group = get_database()
+                    .read()
+                    .map(|db| db.settings.auto_group)
+                    .unwrap_or_default();
+
+                if let Err(e) = set_auto_group(!auto_group) {
+                    report(e);
+                }
+            }
+            "new_profile" => {
+                if let Err(e) = create_profile(app.clone()) {
+                    report(e);
+                }
+            }
+            id if id.starts_with("profile:") => {
+                let name = id.trim_start_matches("profile:").to_owned();
+
+                if let Err(e) = set_profile(app.clone(), name) {
+                    report(e);
+                }
+            }
+            id if id.starts_with("group:") => {
+                if let Ok(id) = id.trim_start_matches("group:").parse() {
+                    if let Err(e) = set_active_group(id) {
+                        report(e);
+                    }
+                }
+            }
             "settings" => {
                 let window = app
                     .get_webview_window("main")
@@ -1,92 +1,346 @@
-import { createResource, createSignal, For, Suspense } from "solid-js";
-import { invoke } from "@tauri-apps/api/core";
+import { createResource, createSignal, For, Show, Suspense } from "solid-js";
+import { invoke, type InvokeArgs } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import "./App.css";
 
 interface Character {
+  id: string;
   name: string;
   breed?: string;
-  enabled: boolean;
+  server?: string;
+  level?: number;
+  notes?: string;
+  // Unix time in seconds
+  last_seen?: number;
+  archived?: boolean;
+  // Only set on group members, per group
+  enabled?: boolean;
+  slot?: number;
+}
+
+// Characters are told apart by their name and server, dragged as JSON
+type CharacterKey = Pick<Character, "name" | "server">
+
+function characterKey(character: Character): string {
+  return JSON.stringify({ name: character.name, server: character.server })
+}
+
+function sameCharacter(a: CharacterKey, b: CharacterKey): boolean {
+  return a.name === b.name && (a.server ?? null) === (b.server ?? null)
+}
+
+function displayName(character: CharacterKey): string {
+  return character.server ? `${character.name} (${character.server})` : character.name
 }
 
 interface Group {
+  id: string;
   name: string;
   characters: Character[]
+  // Defaults to the first member
+  leader?: string;
+}
+
+type Action = string | { Focus: number } | { Macro: string };
+
+interface Binding {
+  chord: string;
+  action: Action;
+}
+
+type FocusStrategy = "Auto" | "Ewmh" | "InputFocus";
+
+interface Settings {
+  focus_timeout: number;
+  focus_strategy: FocusStrategy;
+  auto_group: boolean;
+  matchers: Matcher[];
+  slot_mode: SlotMode;
+  notify_offline: boolean;
+  leader_fallback: LeaderFallback;
+  busy_policy: BusyPolicy;
 }
 
+type LeaderFallback = "FirstOnline" | "Stop"
+
+type BusyPolicy = "Queue" | "Drop" | "Preempt"
+
+type SlotMode = "Fixed" | "Compact"
+
+interface Matcher {
+  class: string;
+  title: string;
+}
+
+interface WindowMatch {
+  character: string;
+  server?: string;
+  breed?: string;
+  level?: number;
+}
+
+interface CommandError {
+  kind: "NotFound" | "Duplicate" | "Invalid" | "Io" | "Lock" | "Parse" | "Conflict";
+  message?: string;
+}
+
+const describeError = (error: unknown) => {
+  if (typeof error === "string") {
+    return error
+  }
+
+  const { kind, message } = error as CommandError
+  return message ? `${kind}: ${message}` : kind
+}
+
+const actionLabel = (action: Action) => typeof action === "string" ? action : "Focus" in action ? `Focus ${action.Focus}` : `Macro ${action.Macro}`
+
+interface Macro {
+  name: string;
+  // Regex whose named groups are available as {name} in texts
+  clipboard?: string;
+  steps: unknown[];
+}
+
+const actions: Action[] = [
+  ...[1, 2, 3, 4, 5, 6, 7, 8].map((slot) => ({ Focus: slot })),
+  "Next", "Previous", "Travel", "TravelFollowers", "Invite", "RightClick", "RightClickFollowers",
+  "LeftClick", "LeftClickFollowers", "DoubleClick", "DoubleClickFollowers", "NextGroup", "PreviousGroup",
+  "PromoteLeader", "Abort",
+]
+
 const breeds = ["Ecaflip", "Eniripsa", "Iop", "Cra", "Feca", "Sacrieur", "Sadida", "Osamodas", "Enutrof", "Sram", "Xelor", "Pandawa", "Roublard", "Zobal", "Steamer", "Eliotrope", "Huppermage", "Ouginak", "Forgelance"]
 
 function App() {
+  const [conflict, setConflict] = createSignal<CommandError | null>(null)
+  const call = async <T,>(command: string, args?: InvokeArgs): Promise<T | undefined> => {
+    try {
+      return await invoke<T>(command, args)
+    } catch (error) {
+      if ((error as CommandError).kind === "Conflict") {
+        setConflict(error as CommandError)
+      } else {
+        alert(describeError(error))
+      }
+    }
+  }
   const [groups, { mutate }] = createResource<Group[]>(async () => (await invoke("get_groups")));
+  const updateGroups = async (command: string, args?: InvokeArgs) => {
+    const groups = await call<Group[]>(command, args)
+    if (groups) {
+      mutate(groups)
+    }
+    return groups
+  }
   const [available_characters, { refetch }] = createResource<Character[]>(async () => (await invoke("get_available_characters")));
   const [selectedGroup, setSelectedGroup] = createSignal(0)
+  const [keymap, { mutate: mutateKeymap }] = createResource<Binding[]>(async () => (await call<Binding[]>("get_keymap")) ?? []);
+  const [settings, { mutate: mutateSettings }] = createResource<Settings | undefined>(async () => (await call<Settings>("get_settings")));
+  const updateSettings = async (changes: Partial<Settings>) => {
+    const updated = await call<Settings>("set_settings", { settings: { ...settings(), ...changes } })
+    if (updated) {
+      mutateSettings(updated)
+      refetchActiveGroup()
+    }
+  }
+  const [activeGroup, { mutate: mutateActiveGroup, refetch: refetchActiveGroup }] = createResource<string | null>(async () => (await invoke("get_active_group")));
+  const updateKeymap = async (bindings: Binding[] | undefined) => {
+    const updated = await call<Binding[]>("set_keymap", { bindings })
+    if (updated) {
+      mutateKeymap(updated)
+    }
+  }
+  const [macros, { mutate: mutateMacros }] = createResource<Macro[]>(async () => (await invoke("get_macros")));
+  const updateMacros = async (updated: Macro[] | undefined) => {
+    const saved = await call<Macro[]>("set_macros", { macros: updated })
+    if (saved) {
+      mutateMacros(saved)
+    }
+  }
+  const updateMacro = (i: number, changes: Partial<Macro>) => updateMacros(macros()?.map((m, j) => j === i ? { ...m, ...changes } : m))
+  // Macros named after an action are played by it
+  const allActions = () => [...actions, ...(macros() ?? []).filter((m) => !actions.includes(m.name)).map((m) => ({ Macro: m.name }))]
+
+  const [characters, { mutate: mutateCharacters }] = createResource<Character[]>(async () => (await invoke("get_characters")));
+  const [stale, setStale] = createSignal<Character[] | null>(null)
+  const lastSeen = (character: Character) => character.last_seen ? new Date(character.last_seen * 1000).toLocaleDateString() : "never seen"
+  const staleFilter = (form: HTMLFormElement) => {
+    const data = new FormData(form)
+    const days = parseInt(data.get("days") as string, 10)
+    return { days: isNaN(days) ? null : days, ungrouped: data.has("ungrouped") }
+  }
+
+  const [windowMatch, setWindowMatch] = createSignal<string>("")
+  const updateMatcher = (i: number, changes: Partial<Matcher>) =>
+    updateSettings({ matchers: settings()?.matchers.map((m, j) => j === i ? { ...m, ...changes } : m) })
+
+  const [profiles] = createResource<string[]>(async () => (await invoke("get_profiles")));
+  const [profile] = createResource<string>(async () => (await invoke("get_profile")));
+  // Everything shown belongs to the previous profile
+  listen("profile-changed", () => location.reload())
+  // data.toml was edited by another program
+  listen("database-changed", () => location.reload())
+  listen<CommandError>("database-conflict", (event) => setConflict(event.payload))
+  const resolveConflict = async (keepLocal: boolean) => {
+    // Resolving returns null, failures undefined
+    if (await call("resolve_conflict", { keepLocal }) !== undefined) {
+      setConflict(null)
+    }
+  }
+  const [databaseWarning, { mutate: mutateDatabaseWarning }] = createResource<string | null>(async () => (await invoke("get_database_warning")));
+  // A save from the tray, a hotkey or a window failed
+  listen<CommandError>("database-error", (event) => mutateDatabaseWarning(describeError(event.payload)))
 
   return (
     <>
+      <Show when={conflict()}>
+        <p class="warning">
+          data.toml was edited elsewhere ({describeError(conflict())})
+          <button style="margin-left: 5px" onclick={() => resolveConflict(true)}>Keep mine</button>
+          <button style="margin-left: 5px" onclick={() => resolveConflict(false)}>Use the file</button>
+        </p>
+      </Show>
+      <Show when={databaseWarning()}>
+        <p class="warning">
+          {databaseWarning()}
+          <button style="margin-left: 5px" onclick={() => call("reload_database")}>Reload</button>
+          <button style="margin-left: 5px" onclick={() => confirm("Move data.toml aside and start over?") && call("reset_database")}>Reset</button>
+          <button style="margin-left: 5px" onclick={() => mutateDatabaseWarning(null)}>Dismiss</button>
+        </p>
+      </Show>
+      <div>
+        <label>Profile
+          <select style="margin-left: 5px" value={profile()} onchange={(e: Event) => call("set_profile", { name: (e.target as HTMLSelectElement).value })}>
+            <For each={profiles()}>{(name) => <option value={name}>{name}</option>}</For>
+          </select>
+        </label>
+        <form style="display: inline; margin-left: 5px" onsubmit={async (e: SubmitEvent) => {
+          e.preventDefault();
+          const form = e.target as HTMLFormElement;
+          const data = new FormData(form);
+          await call("set_profile", { name: data.get("profile") })
+        }}>
+          <input type="text" name="profile" required pattern="[\w\-]+" placeholder="New profile" />
+        </form>
+      </div>
       <div>
         <form onsubmit={async (e: SubmitEvent) => {
           e.preventDefault();
           const form = e.target as HTMLFormElement;
           const data = new FormData(form);
-          const groups: Group[] = await invoke("create_group", Object.fromEntries(data));
-          mutate(groups);
-          setSelectedGroup(0)
-          form.reset();
+          if (await updateGroups("create_group", Object.fromEntries(data))) {
+            setSelectedGroup(0)
+            form.reset();
+          }
         }}>
           <input type="text" name="name" required minlength="3" />
         </form>
+        <label>
+          <input type="checkbox" checked={settings()?.auto_group} onchange={async (e: Event) => {
+            const value = (e.target as HTMLInputElement).checked
+            const auto_group = await call<boolean>("set_auto_group", { value })
+            if (auto_group !== undefined) {
+              mutateSettings({ ...settings()!, auto_group })
+              refetchActiveGroup()
+            }
+          }} />
+          Auto select the group with the most characters online
+        </label>
       </div>
       <Suspense>
         <For each={groups()}>{(group, i) =>
           <div>
             <label for={group.name}>
               <h2>{group.name}
+                <button style="margin-left: 5px" disabled={!settings()?.auto_group && activeGroup() === group.id} onclick={async () => {
+                  const id = await call<string>("set_active_group", { id: group.id })
+                  if (id) {
+                    mutateActiveGroup(id)
+                    mutateSettings({ ...settings()!, auto_group: false })
+                  }
+                }}>
+                  {!settings()?.auto_group && activeGroup() === group.id ? "Active" : "Activate"}
+                </button>
                 <button style="margin-left: 5px" onclick={async () => {
-                  const groups: Group[] = await invoke("delete_group", { id: i() });
-                  mutate(groups);
-                  setSelectedGroup(0)
+                  if (await updateGroups("delete_group", { id: group.id })) {
+                    setSelectedGroup(0)
+                  }
                 }}>
                   Delete
                 </button>
+                <button style="margin-left: 5px" onclick={async () => {
+                  const name = prompt("Group name", group.name)
+                  if (name !== null) {
+                    await updateGroups("rename_group", { id: group.id, name })
+                  }
+                }}>
+                  Rename
+                </button>
+                <button style="margin-left: 5px" onclick={() => updateGroups("duplicate_group", { id: group.id, name: null })}>
+                  Duplicate
+                </button>
+                <button style="margin-left: 5px" disabled={i() === 0} onclick={async () => {
+                  if (await updateGroups("move_group", { id: group.id, index: i() - 1 })) {
+                    setSelectedGroup(i())
+                  }
+                }}>
+                  ↑
+                </button>
+                <button style="margin-left: 5px" disabled={i() === (groups()?.length ?? 0) - 1} onclick={async () => {
+                  if (await updateGroups("move_group", { id: group.id, index: i() + 1 })) {
+                    setSelectedGroup(i())
+                  }
+                }}>
+                  ↓
+                </button>
               </h2>
             </label>
             <input type="radio" id={group.name} name="group" value={group.name} checked={i() === selectedGroup()} />
             <div class="content">
               <div class="group-settings">
                 <div>
-                  <h4 ondragover={(e: DragEvent) => e.preventDefault()} ondrop={async (e: DragEvent) => {
-                    const name = e.dataTransfer?.getData("application/group-available")
-                    if (!name) {
+                  <h4 title="Click a character to skip it, right click to make it the leader" ondragover={(e: DragEvent) => e.preventDefault()} ondrop={async (e: DragEvent) => {
+                    const key = e.dataTransfer?.getData("application/group-available")
+                    if (!key) {
                       return
                     }
 
-                    const groups: Group[] = await invoke("add_character_to_group", { id: i(), name })
-                    mutate(groups)
+                    const { name, server } = JSON.parse(key) as CharacterKey
+                    await updateGroups("add_character_to_group", { id: group.id, name, server })
                   }}>Selected</h4>
                   <div>
                     <For each={group.characters}>
-                      {(character, characterId) =>
-                        <div title={character.name} classList={{ avatar: true, disabled: !character.enabled }} draggable ondragstart={(e: DragEvent) => {
-                          e.dataTransfer?.setData("application/group-selected", characterId().toString())
-                          e.dataTransfer?.setData("application/group-selected-pos", character.name)
+                      {(character) =>
+                        <div title={character.notes ? `${displayName(character)}\n${character.notes}` : displayName(character)} classList={{ avatar: true, disabled: !character.enabled, leader: group.leader === character.id }} draggable ondragstart={(e: DragEvent) => {
+                          e.dataTransfer?.setData("application/group-selected", character.id)
+                          e.dataTransfer?.setData("application/group-selected-pos", characterKey(character))
 
 
                         }} ondragover={(e: DragEvent) => e.preventDefault()} ondrop={async (e: DragEvent) => {
-                          const name = e.dataTransfer?.getData("application/group-available") || e.dataTransfer?.getData("application/group-selected-pos")
+                          const key = e.dataTransfer?.getData("application/group-available") || e.dataTransfer?.getData("application/group-selected-pos")
+                          if (!key) {
+                            return
+                          }
 
-                          if (!name || name === character.name) {
+                          const { name, server } = JSON.parse(key) as CharacterKey
+                          if (sameCharacter({ name, server }, character)) {
                             return
                           }
 
                           let { x, width } = (e.target as HTMLElement).getBoundingClientRect()
                           const middleX = x + width / 2
-                          const groups: Group[] = await invoke("add_character_to_group_at", { id: i(), name, targetName: character.name, right: e.clientX > middleX })
-                          mutate(groups)
+                          await updateGroups("add_character_to_group_at", { id: group.id, name, server, targetId: character.id, right: e.clientX > middleX })
 
                         }} onclick={async () => {
-                          const groups: Group[] = await invoke("set_character_enabled", { id: i(), characterId: characterId(), value: !character.enabled })
-                          mutate(groups)
+                          await updateGroups("set_character_enabled", { id: group.id, characterId: character.id, value: !character.enabled })
+                        }} oncontextmenu={async (e: MouseEvent) => {
+                          e.preventDefault()
+                          await updateGroups("set_group_leader", { id: group.id, characterId: character.id })
                         }}>
                           <img src={`/breeds/${character.breed || 'None'}.png`} />
+                          <input type="number" class="slot" title="Hotkey slot" min="1" value={character.slot} onclick={(e: MouseEvent) => e.stopPropagation()} onchange={async (e: Event) => {
+                            await updateGroups("set_character_slot", { id: group.id, characterId: character.id, slot: parseInt((e.target as HTMLInputElement).value, 10) || 0 })
+                          }} />
                         </div>
                       }
                     </For>
@@ -99,8 +353,7 @@ function App() {
                       return
                     }
 
-                    const groups: Group[] = await invoke("remove_character_from_group", { id: i(), characterId: parseInt(characterId, 10) })
-                    mutate(groups)
+                    await updateGroups("remove_character_from_group", { id: group.id, characterId })
                   }}>
                     Available
                     <button style="margin-left: 5px" onclick={() => {
@@ -109,24 +362,27 @@ function App() {
                     }}>Refresh</button>
                   </h4>
                   <div>
-                    <For each={available_characters()?.filter((ac) => !group.characters.some((c) => c.name === ac.name))}>
+                    <For each={available_characters()?.filter((ac) => !group.characters.some((c) => sameCharacter(c, ac)))}>
                       {(character) => {
                         const [open, setOpen] = createSignal(false);
 
                         return <>
-                          <div title={character.name} class="avatar" onclick={() => setOpen(true)} draggable ondragstart={(e: DragEvent) => {
-                            e.dataTransfer?.setData("application/group-available", character.name)
+                          <div title={character.notes ? `${displayName(character)}\n${character.notes}` : displayName(character)} class="avatar" onclick={() => setOpen(true)} draggable ondragstart={(e: DragEvent) => {
+                            e.dataTransfer?.setData("application/group-available", characterKey(character))
 
                           }}>
                             <img src={`/breeds/${character.breed || 'None'}.png`} />
                           </div>
                           <dialog open={open()}>
-                            <h4>{character.name}</h4>
+                            <h4>{displayName(character)}</h4>
+                            <input type="text" placeholder="Notes" value={character.notes ?? ""} onchange={async (e: Event) => {
+                              await updateGroups("set_character_notes", { id: character.id, notes: (e.target as HTMLInputElement).value })
+                              refetch()
+                            }} />
                             <For each={breeds}>
                               {(breed) =>
                                 <div class="avatar" onclick={async () => {
-                                  const groups: Group[] = await invoke("set_character_breed", { breed, name: character.name })
-                                  mutate(groups)
+                                  await updateGroups("set_character_breed", { id: character.id, breed })
                                   refetch()
                                   setOpen(false)
                                 }}>
@@ -146,6 +402,232 @@ function App() {
         }
         </For>
       </Suspense>
+      <details>
+        <summary>Hotkeys</summary>
+        <Suspense>
+          <For each={keymap()}>{(binding, i) =>
+            <div>
+              <label>{actionLabel(binding.action)}
+                <input type="text" style="margin-left: 5px" value={binding.chord} onchange={async (e: Event) => {
+                  const input = e.target as HTMLInputElement
+                  await updateKeymap(keymap()?.map((b, j) => j === i() ? { ...b, chord: input.value } : b))
+                  input.value = keymap()?.[i()]?.chord ?? input.value
+                }} />
+              </label>
+              <button style="margin-left: 5px" onclick={() => updateKeymap(keymap()?.filter((_, j) => j !== i()))}>Remove</button>
+            </div>
+          }
+          </For>
+        </Suspense>
+        <form onsubmit={async (e: SubmitEvent) => {
+          e.preventDefault();
+          const form = e.target as HTMLFormElement;
+          const data = new FormData(form);
+          const action = allActions()[parseInt(data.get("action") as string, 10)]
+          await updateKeymap([...(keymap() ?? []), { chord: data.get("chord") as string, action }])
+          form.reset();
+        }}>
+          <select name="action">
+            <For each={allActions()}>{(action, i) => <option value={i()}>{actionLabel(action)}</option>}</For>
+          </select>
+          <input type="text" name="chord" required placeholder="Ctrl+Shift+F3" style="margin-left: 5px" />
+          <button type="submit" style="margin-left: 5px">Add</button>
+        </form>
+        <button onclick={async () => {
+          const updated = await call<Binding[]>("reset_keymap")
+          if (updated) {
+            mutateKeymap(updated)
+          }
+        }}>Reset</button>
+      </details>
+      <details>
+        <summary>Macros</summary>
+        <p>Steps: ForEach (include_leader, focus, steps), FocusLeader, TypeText (text), Copy (text), Paste, Key (key, direction), Click (button, count), Wait (ms). Texts may use {"{character}"} in ForEach and the groups of the clipboard regex.</p>
+        <Suspense>
+          <For each={macros()}>{(macro, i) =>
+            <div>
+              <input type="text" placeholder="Name" value={macro.name} onchange={(e: Event) => updateMacro(i(), { name: (e.target as HTMLInputElement).value })} />
+              <input type="text" style="margin-left: 5px" placeholder="Clipboard regex" value={macro.clipboard ?? ""} onchange={(e: Event) => updateMacro(i(), { clipboard: (e.target as HTMLInputElement).value || undefined })} />
+              <button style="margin-left: 5px" onclick={() => updateMacros([...(macros() ?? []), { ...macro, name: `${macro.name} (copy)` }])}>Copy</button>
+              <button style="margin-left: 5px" onclick={() => updateMacros(macros()?.filter((_, j) => j !== i()))}>Remove</button>
+              <textarea style="display: block; width: 100%" rows="6" value={JSON.stringify(macro.steps, null, 2)} onchange={async (e: Event) => {
+                const textarea = e.target as HTMLTextAreaElement
+                try {
+                  await updateMacro(i(), { steps: JSON.parse(textarea.value) })
+                } catch (error) {
+                  alert(`invalid steps: ${error}`)
+                }
+              }} />
+            </div>
+          }
+          </For>
+        </Suspense>
+        <button onclick={() => updateMacros([...(macros() ?? []), { name: "New macro", steps: [] }])}>Add</button>
+        <button style="margin-left: 5px" onclick={async () => {
+          const updated = await call<Macro[]>("reset_macros")
+          if (updated) {
+            mutateMacros(updated)
+          }
+        }}>Reset</button>
+      </details>
+      <details>
+        <summary>Characters</summary>
+        <Suspense>
+          <For each={characters()}>{(character) =>
+            <div>
+              <label>
+                <input type="checkbox" checked={character.archived} onchange={async (e: Event) => {
+                  const updated = await call<Character[]>("set_character_archived", { id: character.id, value: (e.target as HTMLInputElement).checked })
+                  if (updated) {
+                    mutateCharacters(updated)
+                    refetch()
+                  }
+                }} />
+                {character.name} ({[character.server, character.level && `level ${character.level}`, lastSeen(character)].filter(Boolean).join(", ")})
+              </label>
+            </div>
+          }
+          </For>
+        </Suspense>
+        <p>Archived characters are hidden from the available ones.</p>
+        <form onsubmit={async (e: SubmitEvent) => {
+          e.preventDefault();
+          setStale(await call<Character[]>("get_stale_characters", staleFilter(e.target as HTMLFormElement)) ?? null)
+        }}>
+          <label>Not seen for
+            <input type="number" name="days" min="0" style="margin: 0 5px; width: 4em" />
+            days
+          </label>
+          <label style="margin-left: 5px"><input type="checkbox" name="ungrouped" />In no group</label>
+          <button type="submit" style="margin-left: 5px">List</button>
+          <button type="button" style="margin-left: 5px" onclick={async (e: MouseEvent) => {
+            const pruned = await call<Character[]>("prune_characters", staleFilter((e.target as HTMLButtonElement).form!))
+            if (pruned) {
+              alert(`Removed ${pruned.length} characters, members of a group are kept`)
+              setStale(null)
+              mutateCharacters(await invoke<Character[]>("get_characters"))
+              refetch()
+            }
+          }}>Remove</button>
+        </form>
+        <Show when={stale()}>
+          <ul>
+            <For each={stale()}>{(character) => <li>{displayName(character)} ({lastSeen(character)})</li>}</For>
+          </ul>
+        </Show>
+      </details>
+      <details>
+        <summary>Import / export</summary>
+        <form onsubmit={async (e: SubmitEvent) => {
+          e.preventDefault();
+          const data = new FormData(e.target as HTMLFormElement);
+          if (await call("export_bundle", { path: data.get("path"), groupIds: data.getAll("group"), keymap: data.has("keymap") }) !== undefined) {
+            alert("Exported")
+          }
+        }}>
+          <For each={groups()}>{(group) =>
+            <label style="margin-right: 5px"><input type="checkbox" name="group" value={group.id} />{group.name}</label>
+          }
+          </For>
+          <label><input type="checkbox" name="keymap" />Hotkeys</label>
+          <div>
+            <input type="text" name="path" required placeholder="~/team.json" />
+            <button type="submit" style="margin-left: 5px">Export</button>
+          </div>
+        </form>
+        <form onsubmit={async (e: SubmitEvent) => {
+          e.preventDefault();
+          const data = new FormData(e.target as HTMLFormElement);
+          await updateGroups("import_bundle", { path: data.get("path"), mode: data.get("mode"), policy: data.get("policy") })
+          refetchActiveGroup()
+        }}>
+          <input type="text" name="path" required placeholder="~/team.json" />
+          <select name="mode" style="margin-left: 5px">
+            <option value="Merge">Add to my groups</option>
+            <option value="Replace">Replace my groups</option>
+          </select>
+          <select name="policy" style="margin-left: 5px">
+            <option value="Skip">Keep mine on duplicate names</option>
+            <option value="Overwrite">Take theirs on duplicate names</option>
+            <option value="Rename">Rename duplicate groups</option>
+          </select>
+          <button type="submit" style="margin-left: 5px">Import</button>
+        </form>
+      </details>
+      <details>
+        <summary>Settings</summary>
+        <Suspense>
+          <div>
+            <label>Focus timeout (ms)
+              <input type="number" style="margin-left: 5px" min="0" value={settings()?.focus_timeout} onchange={(e: Event) => updateSettings({ focus_timeout: parseInt((e.target as HTMLInputElement).value, 10) || 0 })} />
+            </label>
+          </div>
+          <div>
+            <label>Focus strategy
+              <select style="margin-left: 5px" value={settings()?.focus_strategy} onchange={(e: Event) => updateSettings({ focus_strategy: (e.target as HTMLSelectElement).value as FocusStrategy })}>
+                <option value="Auto">Auto</option>
+                <option value="Ewmh">Window manager (_NET_ACTIVE_WINDOW)</option>
+                <option value="InputFocus">Input focus</option>
+              </select>
+            </label>
+          </div>
+          <div>
+            <label>Focus hotkeys
+              <select style="margin-left: 5px" value={settings()?.slot_mode} onchange={(e: Event) => updateSettings({ slot_mode: (e.target as HTMLSelectElement).value as SlotMode })}>
+                <option value="Fixed">Slot of each character</option>
+                <option value="Compact">N-th online character</option>
+              </select>
+            </label>
+          </div>
+          <div>
+            <label>
+              <input type="checkbox" checked={settings()?.notify_offline} onchange={(e: Event) => updateSettings({ notify_offline: (e.target as HTMLInputElement).checked })} />
+              Notify when the character of a slot is offline
+            </label>
+          </div>
+          <div>
+            <label>When the leader is offline
+              <select style="margin-left: 5px" value={settings()?.leader_fallback} onchange={(e: Event) => updateSettings({ leader_fallback: (e.target as HTMLSelectElement).value as LeaderFallback })}>
+                <option value="FirstOnline">The first online character leads</option>
+                <option value="Stop">Travel, invite and clicks do nothing</option>
+              </select>
+            </label>
+          </div>
+          <div>
+            <label>Hotkeys pressed while another one runs
+              <select style="margin-left: 5px" value={settings()?.busy_policy} onchange={(e: Event) => updateSettings({ busy_policy: (e.target as HTMLSelectElement).value as BusyPolicy })}>
+                <option value="Queue">Run after it</option>
+                <option value="Drop">Are ignored</option>
+                <option value="Preempt">Cancel it</option>
+              </select>
+            </label>
+          </div>
+          <h4>Dofus windows</h4>
+          <p>The class is matched against WM_CLASS, the title must capture the character as (?P&lt;character&gt;...) and may capture (?P&lt;server&gt;...) and (?P&lt;breed&gt;...). These rules are tried before the built-in ones for Dofus 2, 3 and Retro, add one for the steam_app_... class of Proton.</p>
+          <For each={settings()?.matchers}>{(matcher, i) =>
+            <div>
+              <input type="text" placeholder="Class" value={matcher.class} onchange={(e: Event) => updateMatcher(i(), { class: (e.target as HTMLInputElement).value })} />
+              <input type="text" style="margin-left: 5px" placeholder="Title" value={matcher.title} onchange={(e: Event) => updateMatcher(i(), { title: (e.target as HTMLInputElement).value })} />
+              <button style="margin-left: 5px" onclick={() => updateSettings({ matchers: settings()?.matchers.filter((_, j) => j !== i()) })}>Remove</button>
+            </div>
+          }
+          </For>
+          <button onclick={() => updateSettings({ matchers: [...(settings()?.matchers ?? []), { class: "(?i)^dofus$", title: "^(?P<character>\\S+) - " }] })}>Add</button>
+          <form style="margin-top: 5px" onsubmit={async (e: SubmitEvent) => {
+            e.preventDefault();
+            const data = new FormData(e.target as HTMLFormElement);
+            const found = await call<WindowMatch | null>("match_window", { matchers: settings()?.matchers, class: data.get("class"), title: data.get("title") })
+            if (found !== undefined) {
+              setWindowMatch(found ? [found.character, found.server, found.breed, found.level && `level ${found.level}`].filter(Boolean).join(", ") : "No character")
+            }
+          }}>
+            <input type="text" name="class" placeholder="dofus.exe" />
+            <input type="text" name="title" style="margin-left: 5px" placeholder="Iop-Leader - Iop - 3.0" />
+            <button type="submit" style="margin-left: 5px">Try</button>
+            <span style="margin-left: 5px">{windowMatch()}</span>
+          </form>
+        </Suspense>
+      </details>
     </>
   );
 }